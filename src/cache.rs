@@ -0,0 +1,242 @@
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::collections::{HashMap, HashSet};
+
+use prelude::*;
+use config::{Config, get_env_cache_root};
+use ctx::Context;
+
+use walkdir::WalkDir;
+
+/// A folder in the cache that is no longer needed.
+#[derive(Debug)]
+pub struct Garbage {
+    pub path: PathBuf,
+    pub reason: String,
+    pub size: u64,
+    /// the folder cannot be attributed to a project so it might still
+    /// be in use.
+    pub unknown: bool,
+}
+
+/// Returns the size of all files in a folder.
+pub fn get_dir_size(path: &Path) -> u64 {
+    let mut rv = 0;
+    for entry in WalkDir::new(path) {
+        if_chain! {
+            if let Ok(entry) = entry;
+            if let Ok(md) = entry.metadata();
+            if md.is_file();
+            then {
+                rv += md.len();
+            }
+        }
+    }
+    rv
+}
+
+/// Formats a number of bytes for humans.
+pub fn format_size(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < units.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, units[unit])
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+enum ProjectRefs {
+    /// the config of the project no longer exists.
+    Gone,
+    /// the config could not be loaded so we cannot tell what it uses.
+    Unknown,
+    /// the paths the project references.
+    Live(HashSet<PathBuf>),
+}
+
+/// Lazily loads the configs of known projects.
+struct ProjectIndex<'a> {
+    ctx: &'a Context,
+    projects: HashMap<PathBuf, ProjectRefs>,
+}
+
+impl<'a> ProjectIndex<'a> {
+    fn new(ctx: &'a Context) -> ProjectIndex<'a> {
+        ProjectIndex {
+            ctx: ctx,
+            projects: HashMap::new(),
+        }
+    }
+
+    fn lookup(&mut self, filename: &Path) -> Result<&ProjectRefs> {
+        if !self.projects.contains_key(filename) {
+            // a profile hides tools so the full config is loaded then
+            let refs = if filename == self.ctx.config().filename() &&
+                          self.ctx.config().profile().is_none() {
+                ProjectRefs::Live(self.ctx.referenced_cache_paths()?)
+            } else if fs::metadata(filename).is_err() {
+                ProjectRefs::Gone
+            } else {
+                // never fetch extended configs of other projects
+                match Config::load(filename, None, true)
+                    .and_then(Context::new)
+                    .and_then(|other| other.referenced_cache_paths())
+                {
+                    Ok(refs) => ProjectRefs::Live(refs),
+                    Err(_) => ProjectRefs::Unknown,
+                }
+            };
+            self.projects.insert(filename.to_path_buf(), refs);
+        }
+        Ok(&self.projects[filename])
+    }
+}
+
+fn read_filename(path: &Path) -> Option<PathBuf> {
+    let mut contents = String::new();
+    if_chain! {
+        if let Ok(mut f) = fs::File::open(path);
+        if f.read_to_string(&mut contents).is_ok();
+        then {
+            Some(PathBuf::from(contents.trim()))
+        } else {
+            None
+        }
+    }
+}
+
+fn add_garbage(rv: &mut Vec<Garbage>, path: PathBuf, reason: String) {
+    let size = get_dir_size(&path);
+    rv.push(Garbage {
+        path: path,
+        reason: reason,
+        size: size,
+        unknown: false,
+    });
+}
+
+fn add_unknown(rv: &mut Vec<Garbage>, path: PathBuf) {
+    add_garbage(rv, path, "unknown project".into());
+    rv.last_mut().unwrap().unknown = true;
+}
+
+fn find_project_garbage(index: &mut ProjectIndex, rv: &mut Vec<Garbage>) -> Result<()> {
+    let root = get_env_cache_root()?;
+    if fs::metadata(&root).is_err() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(&root)? {
+        let project_dir = entry?.path();
+        if !project_dir.is_dir() {
+            continue;
+        }
+
+        let filename = if project_dir == index.ctx.cache_dir() {
+            index.ctx.config().filename().to_path_buf()
+        } else {
+            match read_filename(&project_dir.join("project")) {
+                Some(filename) => filename,
+                // caches from before projects were recorded
+                None => {
+                    add_unknown(rv, project_dir);
+                    continue;
+                }
+            }
+        };
+
+        match *index.lookup(&filename)? {
+            ProjectRefs::Gone => {
+                add_garbage(rv, project_dir,
+                            format!("{} no longer exists", filename.display()));
+            }
+            // projects with broken configs are left alone
+            ProjectRefs::Unknown => {}
+            ProjectRefs::Live(ref referenced) => {
+                for &(folder, kind) in &[("rt", "runtime"), ("tools", "include")] {
+                    let folder = project_dir.join(folder);
+                    if fs::metadata(&folder).is_err() {
+                        continue;
+                    }
+                    for entry in fs::read_dir(&folder)? {
+                        let path = entry?.path();
+                        if !referenced.contains(&path) {
+                            add_garbage(rv, path, format!("unreferenced {}", kind));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn find_store_garbage(index: &mut ProjectIndex, rv: &mut Vec<Garbage>) -> Result<()> {
+    let root = index.ctx.store_dir().join("rt");
+    if fs::metadata(&root).is_err() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(&root)? {
+        let path = entry?.path();
+        let mut used = false;
+
+        // runtimes installed before references were tracked
+        let refs = match fs::read_dir(path.join(".calm-refs")) {
+            Ok(refs) => refs,
+            Err(_) => {
+                add_unknown(rv, path);
+                continue;
+            }
+        };
+
+        for entry in refs {
+            let filename = match read_filename(&entry?.path()) {
+                Some(filename) => filename,
+                None => continue,
+            };
+            used = match *index.lookup(&filename)? {
+                ProjectRefs::Gone => false,
+                ProjectRefs::Unknown => true,
+                ProjectRefs::Live(ref referenced) => referenced.contains(&path),
+            };
+            if used {
+                break;
+            }
+        }
+
+        if !used {
+            add_garbage(rv, path, "unreferenced runtime".into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Finds all runtimes and includes in the cache and the shared store
+/// that are not referenced by the current config or any other known
+/// project.  Caches that cannot be attributed to a project are
+/// returned as unknown.
+pub fn find_garbage(ctx: &Context) -> Result<Vec<Garbage>> {
+    let mut rv = vec![];
+    let mut index = ProjectIndex::new(ctx);
+    find_project_garbage(&mut index, &mut rv)?;
+    find_store_garbage(&mut index, &mut rv)?;
+    Ok(rv)
+}
+
+/// Deletes a previously found piece of garbage.
+pub fn remove_garbage(garbage: &Garbage) -> Result<()> {
+    if fs::metadata(&garbage.path).is_ok() {
+        fs::remove_dir_all(&garbage.path)?;
+    }
+    Ok(())
+}
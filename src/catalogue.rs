@@ -0,0 +1,204 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::collections::BTreeMap;
+use std::os::unix::fs::PermissionsExt;
+
+use prelude::*;
+use utils::ignore::GitIgnore;
+
+use walkdir::{DirEntry, WalkDir};
+
+
+/// A file of a tool template.
+pub struct TemplateFile {
+    pub name: &'static str,
+    pub contents: &'static str,
+    pub executable: bool,
+}
+
+/// A tool `calm init` can set up.
+pub struct ToolTemplate {
+    pub id: &'static str,
+    pub language: &'static str,
+    pub description: &'static str,
+    pub files: &'static [TemplateFile],
+}
+
+const RUSTFMT_TOOL: &'static str = "\
+tool:
+  format:
+    patterns:
+      - '*.rs'
+    run:
+      - cmd: rustfmt
+        description: Formatting with rustfmt
+";
+
+const GOFMT_TOOL: &'static str = "\
+tool:
+  format:
+    patterns:
+      - '*.go'
+    run:
+      - cmd: gofmt -w
+        description: Formatting with gofmt
+";
+
+/// All tools `calm init` knows about.  The javascript and python
+/// tools are the ones of the demo project.
+pub static CATALOGUE: &'static [ToolTemplate] = &[
+    ToolTemplate {
+        id: "flake8",
+        language: "python",
+        description: "Lints python code with flake8",
+        files: &[
+            TemplateFile {
+                name: "calmtool.yml",
+                contents: include_str!("../demo/.calm/flake8/calmtool.yml"),
+                executable: false,
+            },
+            TemplateFile {
+                name: "run-flake8.py",
+                contents: include_str!("../demo/.calm/flake8/run-flake8.py"),
+                executable: true,
+            },
+            TemplateFile {
+                name: ".flake8",
+                contents: include_str!("../demo/.calm/flake8/.flake8"),
+                executable: false,
+            },
+        ],
+    },
+    ToolTemplate {
+        id: "eslint",
+        language: "javascript",
+        description: "Lints javascript code with eslint",
+        files: &[
+            TemplateFile {
+                name: "calmtool.yml",
+                contents: include_str!("../demo/.calm/eslint/calmtool.yml"),
+                executable: false,
+            },
+            TemplateFile {
+                name: "run-eslint.js",
+                contents: include_str!("../demo/.calm/eslint/run-eslint.js"),
+                executable: true,
+            },
+            TemplateFile {
+                name: "eslintformatter.js",
+                contents: include_str!("../demo/.calm/eslint/eslintformatter.js"),
+                executable: false,
+            },
+            TemplateFile {
+                name: ".eslintrc.json",
+                contents: include_str!("../demo/.calm/eslint/.eslintrc.json"),
+                executable: false,
+            },
+            TemplateFile {
+                name: ".eslintignore",
+                contents: include_str!("../demo/.calm/eslint/.eslintignore"),
+                executable: false,
+            },
+        ],
+    },
+    ToolTemplate {
+        id: "prettier",
+        language: "javascript",
+        description: "Formats javascript code with prettier",
+        files: &[
+            TemplateFile {
+                name: "calmtool.yml",
+                contents: include_str!("../demo/.calm/prettier/calmtool.yml"),
+                executable: false,
+            },
+        ],
+    },
+    ToolTemplate {
+        id: "rustfmt",
+        language: "rust",
+        description: "Formats rust code with the installed rustfmt",
+        files: &[
+            TemplateFile {
+                name: "calmtool.yml",
+                contents: RUSTFMT_TOOL,
+                executable: false,
+            },
+        ],
+    },
+    ToolTemplate {
+        id: "gofmt",
+        language: "go",
+        description: "Formats go code with the installed gofmt",
+        files: &[
+            TemplateFile {
+                name: "calmtool.yml",
+                contents: GOFMT_TOOL,
+                executable: false,
+            },
+        ],
+    },
+];
+
+fn language_for_extension(ext: &str) -> Option<&'static str> {
+    match ext {
+        "py" => Some("python"),
+        "js" | "jsx" | "mjs" | "ts" | "tsx" => Some("javascript"),
+        "rs" => Some("rust"),
+        "go" => Some("go"),
+        "rb" => Some("ruby"),
+        "java" => Some("java"),
+        "c" | "h" | "cc" | "cpp" | "hpp" => Some("c"),
+        _ => None,
+    }
+}
+
+fn is_skipped_dir(entry: &DirEntry) -> bool {
+    entry.depth() > 0 && entry.file_type().is_dir() && entry.file_name()
+        .to_str()
+        .map(|x| x.starts_with('.') || x == "node_modules" || x == "target")
+        .unwrap_or(false)
+}
+
+/// Counts the source files per language below a folder.  Files git
+/// ignores are not counted.
+pub fn detect_languages(root: &Path) -> BTreeMap<&'static str, usize> {
+    let gitignore = GitIgnore::discover(root);
+    let mut rv = BTreeMap::new();
+    let walker = WalkDir::new(root).into_iter().filter_entry(|x| {
+        !is_skipped_dir(x) &&
+            !gitignore.as_ref().map(|ign| ign.is_ignored(x.path())).unwrap_or(false)
+    });
+    for entry in walker.filter_map(|x| x.ok()) {
+        if_chain! {
+            if entry.file_type().is_file();
+            if let Some(ext) = entry.path().extension().and_then(|x| x.to_str());
+            if let Some(language) = language_for_extension(ext);
+            then {
+                *rv.entry(language).or_insert(0) += 1;
+            }
+        }
+    }
+    rv
+}
+
+/// Returns the templates for a language.
+pub fn find_templates(language: &str) -> Vec<&'static ToolTemplate> {
+    CATALOGUE.iter().filter(|x| x.language == language).collect()
+}
+
+impl ToolTemplate {
+    /// Writes the files of the template into a tool folder.
+    pub fn write_to(&self, tool_dir: &Path) -> Result<()> {
+        fs::create_dir_all(tool_dir)?;
+        for file in self.files {
+            let path = tool_dir.join(file.name);
+            let mut f = fs::File::create(&path)?;
+            f.write_all(file.contents.as_bytes())?;
+            if file.executable {
+                fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+            }
+        }
+        Ok(())
+    }
+}
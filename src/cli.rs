@@ -1,35 +1,135 @@
 use std::env;
+use std::fs;
 use std::io;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process;
 
 use prelude::*;
-use config::Config;
-use ctx::Context;
+use config::{Config, find_config_file, is_offline_env, read_section_keys};
+use ctx::{Context, ToolFilter};
+use schema::{config_schema, tool_config_schema, validate_file};
+use workspace::Workspace;
+use environ::EnvFormat;
+use catalogue::{detect_languages, find_templates};
+use doctor::diagnose;
+use cache::{find_garbage, remove_garbage, format_size};
 use report::Format;
 use utils::whatchanged::get_changed_files;
 use utils::hooks::HookManager;
 use utils::watch::watch_files;
-use utils::ui::clear_term;
+use utils::ui::{clear_term, prompt_yes_no};
+use utils::cmd::CommandBuilder;
+use utils::git::find_repo_root;
 
-use console::style;
+use console::{style, user_attended};
+use serde_json;
 use clap::{App, Arg, AppSettings, ArgMatches};
 
 const ABOUT: &'static str = "
 Calm makes your development experience delightful.";
 
-fn execute(args: Vec<String>, config: Config) -> Result<()> {
-    let app = App::new("calm")
+fn tool_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("tool")
+        .long("tool")
+        .value_name("TOOL")
+        .multiple(true)
+        .number_of_values(1)
+        .help("Only run this tool.  Can be given multiple times.")
+}
+
+fn skip_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("skip")
+        .long("skip")
+        .value_name("TOOL")
+        .multiple(true)
+        .number_of_values(1)
+        .help("Do not run this tool.  Can be given multiple times.")
+}
+
+fn get_values(matches: &ArgMatches, name: &str) -> Vec<String> {
+    matches.values_of(name)
+        .map(|values| values.map(|x| x.to_string()).collect())
+        .unwrap_or_else(Vec::new)
+}
+
+fn get_tool_filter(matches: &ArgMatches) -> ToolFilter {
+    ToolFilter::new(get_values(matches, "tool"), get_values(matches, "skip"))
+}
+
+fn make_app() -> App<'static, 'static> {
+    App::new("calm")
         .about(ABOUT)
         .max_term_width(100)
         .setting(AppSettings::VersionlessSubcommands)
         .setting(AppSettings::SubcommandRequiredElseHelp)
         .global_setting(AppSettings::UnifiedHelpMessage)
+        .arg(Arg::with_name("offline")
+             .long("offline")
+             .global(true)
+             .help("Never access the network.  Installs only use local caches \
+                    and the configured wheelhouses.  Can also be enabled with \
+                    CALM_OFFLINE=1."))
+        .arg(Arg::with_name("profile")
+             .long("profile")
+             .value_name("PROFILE")
+             .global(true)
+             .help("Only use the tools of a profile from the config.  Can also \
+                    be set with CALM_PROFILE."))
+        .subcommand(App::new("init")
+            .about("Sets up calm for the project in the current folder")
+            .arg(Arg::with_name("yes")
+                 .long("yes")
+                 .short("y")
+                 .help("Add all suggested tools without asking."))
+            .arg(Arg::with_name("hook")
+                 .long("hook")
+                 .help("Also install the git pre-commit hook."))
+            .arg(Arg::with_name("force")
+                 .long("force")
+                 .help("Replace an existing calm.yml.  Only calm.yml and the \
+                        selected tools are rewritten.")))
         .subcommand(App::new("update")
-            .about("Update all calm toolchains"))
+            .about("Update all calm toolchains")
+            .arg(Arg::with_name("force")
+                 .long("force")
+                 .help("Reinstall all toolchains even if they are up to date."))
+            .arg(Arg::with_name("relock")
+                 .long("relock")
+                 .help("Resolve the revisions of git includes again instead of \
+                        checking out the commits from the lock file."))
+            .arg(tool_arg())
+            .arg(skip_arg()))
         .subcommand(App::new("clear-cache")
             .about("Clears the runtime cache"))
+        .subcommand(App::new("cache")
+            .about("Manages the runtime cache")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(App::new("gc")
+                .about("Removes runtimes and includes that are no longer referenced")
+                .arg(Arg::with_name("dry_run")
+                     .long("dry-run")
+                     .help("Only report what would be removed."))
+                .arg(Arg::with_name("remove_unknown")
+                     .long("remove-unknown")
+                     .help("Also remove caches that do not belong to a known project."))))
+        .subcommand(App::new("config")
+            .about("Inspects the configuration")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(App::new("show")
+                .about("Prints the merged config with the origin of each value"))
+            .subcommand(App::new("check")
+                .about("Validates a calm.yml or calmtool.yml and reports all problems")
+                .arg(Arg::with_name("path")
+                     .index(1)
+                     .value_name("PATH")
+                     .help("The file to check.  Defaults to the config of the \
+                            current project.")))
+            .subcommand(App::new("schema")
+                .about("Prints the JSON Schema of the config for editors")
+                .arg(Arg::with_name("tool")
+                     .long("tool")
+                     .help("Print the schema of calmtool.yml instead."))))
         .subcommand(App::new("hook")
             .about("Manages the git hook integration")
             .arg(Arg::with_name("install")
@@ -47,7 +147,8 @@ fn execute(args: Vec<String>, config: Config) -> Result<()> {
                  .long("format")
                  .short("f")
                  .value_name("FORMAT")
-                 .possible_values(&["human", "human-extended", "simple", "checkstyle"])
+                 .possible_values(&["human", "human-extended", "simple", "checkstyle",
+                                    "sarif"])
                  .help("Sets the output format"))
             .arg(Arg::with_name("watch")
                  .long("watch")
@@ -61,6 +162,8 @@ fn execute(args: Vec<String>, config: Config) -> Result<()> {
             .arg(Arg::with_name("changed_files")
                  .long("changed-files")
                  .help("Lint files changed in the current git work tree."))
+            .arg(tool_arg())
+            .arg(skip_arg())
             .arg(Arg::with_name("files")
                 .index(1)
                 .multiple(true)))
@@ -72,44 +175,199 @@ fn execute(args: Vec<String>, config: Config) -> Result<()> {
             .arg(Arg::with_name("changed_files")
                  .long("changed-files")
                  .help("Format files changed in the current git work tree."))
+            .arg(tool_arg())
+            .arg(skip_arg())
             .arg(Arg::with_name("files")
                 .index(1)
                 .multiple(true)))
+        .subcommand(App::new("tools")
+            .about("Lists the configured tools and their status")
+            .arg(Arg::with_name("fmt")
+                 .long("format")
+                 .short("f")
+                 .value_name("FORMAT")
+                 .possible_values(&["human", "json"])
+                 .help("Sets the output format")))
+        .subcommand(App::new("explain")
+            .about("Shows the documentation of a lint rule")
+            .arg(Arg::with_name("rule")
+                 .index(1)
+                 .required(true)
+                 .value_name("TOOL:CODE")
+                 .help("The rule as shown in the lint output, for instance \
+                        flake8:E501.")))
+        .subcommand(App::new("doctor")
+            .about("Checks the environment calm and its tools need"))
+        .subcommand(App::new("run")
+            .about("Runs a task from the config or lists all tasks")
+            .setting(AppSettings::TrailingVarArg)
+            .arg(Arg::with_name("task")
+                 .index(1)
+                 .value_name("TASK")
+                 .help("The task to run.  Lists all tasks if not given."))
+            .arg(Arg::with_name("args")
+                 .index(2)
+                 .multiple(true)
+                 .value_name("ARGS")
+                 .help("Extra arguments passed to the last command of the task.")))
+        .subcommand(App::new("exec")
+            .about("Runs a command in the environment of the tools")
+            .setting(AppSettings::TrailingVarArg)
+            .arg(tool_arg())
+            .arg(skip_arg())
+            .arg(Arg::with_name("cmd")
+                 .index(1)
+                 .required(true)
+                 .multiple(true)
+                 .value_name("COMMAND")
+                 .help("The command and its arguments.")))
+        .subcommand(App::new("env")
+            .about("Prints the environment of the tools for shells and editors")
+            .arg(tool_arg())
+            .arg(skip_arg())
+            .arg(Arg::with_name("fmt")
+                 .long("format")
+                 .short("f")
+                 .value_name("FORMAT")
+                 .possible_values(&["sh", "fish", "json", "dotenv"])
+                 .help("Sets the output format (defaults to sh)")))
+        .subcommand(App::new("shell")
+            .about("Spawns a shell in the environment of the tools")
+            .arg(tool_arg())
+            .arg(skip_arg()))
         .subcommand(App::new("which")
             .about("Given a command returns the path where it lives.")
             .arg(Arg::with_name("cmd")
                  .index(1)
                  .value_name("COMMAND")
                  .required(true)
-                 .help("The command to find")));
+                 .help("The command to find")))
+        .subcommand(App::new("completions")
+            .about("Prints the shell completion script")
+            .arg(Arg::with_name("shell")
+                 .index(1)
+                 .required(true)
+                 .value_name("SHELL")
+                 .possible_values(&["bash", "zsh", "fish"])
+                 .help("The shell to print completions for.")))
+        .subcommand(App::new("__complete")
+            .setting(AppSettings::Hidden)
+            .arg(Arg::with_name("kind")
+                 .index(1)
+                 .required(true)
+                 .possible_values(&["tools", "tasks"])))
+}
 
-    let matches = app.get_matches_from_safe(args)?;
-    let mut ctx = Context::new(config)?;
+fn execute(args: Vec<String>) -> Result<()> {
+    let matches = make_app().get_matches_from_safe(args)?;
 
-    if let Some(_sub_matches) = matches.subcommand_matches("update") {
-        cmd_update_installation(&mut ctx)
+    // these work without a valid config
+    if let Some(sub_matches) = matches.subcommand_matches("completions") {
+        return cmd_completions(sub_matches);
+    }
+    if let Some(sub_matches) = matches.subcommand_matches("__complete") {
+        return cmd_complete(sub_matches);
+    }
+    if let Some(sub_matches) = matches.subcommand_matches("init") {
+        return cmd_init(sub_matches);
+    }
+    if let Some(sub_matches) = matches.subcommand_matches("config") {
+        if let Some(check_matches) = sub_matches.subcommand_matches("check") {
+            return cmd_config_check(check_matches);
+        } else if let Some(schema_matches) = sub_matches.subcommand_matches("schema") {
+            return cmd_config_schema(schema_matches);
+        }
+    }
+
+    let profile = matches.value_of("profile")
+        .or_else(|| matches.subcommand().1.and_then(|x| x.value_of("profile")))
+        .map(|x| x.to_string())
+        .or_else(|| env::var("CALM_PROFILE").ok().and_then(|x| {
+            if x.is_empty() { None } else { Some(x) }
+        }));
+    // only commands that work on the files of a project need the
+    // projects nested in it
+    let nested = match matches.subcommand() {
+        ("lint", _) | ("format", _) | ("update", _) => true,
+        ("hook", Some(sub_matches)) => sub_matches.is_present("pre_commit"),
+        _ => false,
+    };
+    let offline = is_offline_env() || matches.is_present("offline") ||
+        matches.subcommand().1.map(|x| x.is_present("offline")).unwrap_or(false);
+    let mut workspace = Workspace::discover(profile.as_ref().map(|x| x.as_str()), nested,
+                                            offline)?;
+
+    if let Some(sub_matches) = matches.subcommand().1 {
+        workspace.set_tool_filter(get_tool_filter(sub_matches))?;
+    }
+
+    if let Some(sub_matches) = matches.subcommand_matches("update") {
+        for ctx in workspace.contexts_mut() {
+            cmd_update_installation(ctx, sub_matches)?;
+        }
+        Ok(())
     } else if let Some(_sub_matches) = matches.subcommand_matches("clear-cache") {
-        cmd_clear_cache(&ctx)
+        for ctx in workspace.contexts() {
+            cmd_clear_cache(ctx)?;
+        }
+        Ok(())
+    } else if let Some(sub_matches) = matches.subcommand_matches("cache") {
+        if let Some(gc_matches) = sub_matches.subcommand_matches("gc") {
+            cmd_cache_gc(workspace.primary(), gc_matches)
+        } else {
+            unreachable!();
+        }
+    } else if let Some(sub_matches) = matches.subcommand_matches("config") {
+        if let Some(_show_matches) = sub_matches.subcommand_matches("show") {
+            cmd_config_show(workspace.primary())
+        } else {
+            unreachable!();
+        }
     } else if let Some(sub_matches) = matches.subcommand_matches("lint") {
+        workspace.ensure_toolchains()?;
         if sub_matches.is_present("watch") {
-            cmd_lint_watch(&ctx, sub_matches)
+            cmd_lint_watch(&workspace, sub_matches)
         } else {
-            cmd_lint(&ctx, sub_matches)
+            cmd_lint(&workspace, sub_matches)
         }
     } else if let Some(sub_matches) = matches.subcommand_matches("format") {
-        cmd_format(&ctx, sub_matches)
+        workspace.ensure_toolchains()?;
+        cmd_format(&workspace, sub_matches)
     } else if let Some(sub_matches) = matches.subcommand_matches("hook") {
-        cmd_hook(&ctx, sub_matches)
+        if sub_matches.is_present("pre_commit") {
+            workspace.use_hook_profile("pre-commit")?;
+            workspace.ensure_toolchains()?;
+        }
+        cmd_hook(&workspace, sub_matches)
+    } else if let Some(sub_matches) = matches.subcommand_matches("tools") {
+        cmd_tools(workspace.primary(), sub_matches)
+    } else if let Some(sub_matches) = matches.subcommand_matches("explain") {
+        cmd_explain(&workspace, sub_matches)
+    } else if let Some(_sub_matches) = matches.subcommand_matches("doctor") {
+        cmd_doctor(&workspace)
+    } else if let Some(sub_matches) = matches.subcommand_matches("run") {
+        cmd_run(workspace.primary_mut(), sub_matches)
+    } else if let Some(sub_matches) = matches.subcommand_matches("exec") {
+        cmd_exec(workspace.primary_mut(), sub_matches)
+    } else if let Some(sub_matches) = matches.subcommand_matches("env") {
+        cmd_env(workspace.primary_mut(), sub_matches)
+    } else if let Some(_sub_matches) = matches.subcommand_matches("shell") {
+        cmd_shell(workspace.primary_mut())
     } else if let Some(sub_matches) = matches.subcommand_matches("which") {
-        cmd_which(&ctx, sub_matches)
+        cmd_which(workspace.primary(), sub_matches)
     } else {
         unreachable!();
     }
 }
 
-fn cmd_update_installation(ctx: &mut Context) -> Result<()> {
-    ctx.pull_dependencies()?;
-    ctx.update()?;
+fn cmd_update_installation(ctx: &mut Context, matches: &ArgMatches) -> Result<()> {
+    let tool_ids = ctx.active_tools();
+    ctx.check_offline(&tool_ids, matches.is_present("force"))?;
+    let changes = ctx.pull_dependencies(matches.is_present("relock"))?;
+    ctx.update(matches.is_present("force"))?;
+    for change in changes {
+        println!("{}", change);
+    }
     Ok(())
 }
 
@@ -118,7 +376,233 @@ fn cmd_clear_cache(ctx: &Context) -> Result<()> {
     Ok(())
 }
 
-fn cmd_lint(ctx: &Context, matches: &ArgMatches) -> Result<()> {
+fn cmd_cache_gc(ctx: &Context, matches: &ArgMatches) -> Result<()> {
+    let dry_run = matches.is_present("dry_run");
+    let remove_unknown = matches.is_present("remove_unknown");
+    let (garbage, unknown): (Vec<_>, Vec<_>) = find_garbage(ctx)?
+        .into_iter()
+        .partition(|x| !x.unknown || remove_unknown);
+    let mut total = 0;
+
+    for item in &unknown {
+        println!("Keeping {} ({}, {})", style(item.path.display()).cyan(),
+                 item.reason, format_size(item.size));
+    }
+    if !unknown.is_empty() {
+        println!("Pass --remove-unknown to remove caches of unknown projects.");
+    }
+
+    for item in &garbage {
+        println!("{} {} ({}, {})",
+                 if dry_run { "Would remove" } else { "Removing" },
+                 style(item.path.display()).cyan(),
+                 item.reason,
+                 format_size(item.size));
+        if !dry_run {
+            remove_garbage(item)?;
+        }
+        total += item.size;
+    }
+
+    if garbage.is_empty() {
+        println!("Nothing to collect.");
+    } else if dry_run {
+        println!("Would free {}.", format_size(total));
+    } else {
+        println!("Freed {}.", format_size(total));
+    }
+    Ok(())
+}
+
+fn cmd_init(matches: &ArgMatches) -> Result<()> {
+    let here = match find_repo_root() {
+        Some(root) => root,
+        None => env::current_dir()?,
+    };
+    let calm_dir = here.join(".calm");
+    if calm_dir.join("calm.yml").is_file() && !matches.is_present("force") {
+        fail!("{} already exists (pass --force to replace it)",
+              calm_dir.join("calm.yml").display());
+    }
+
+    let languages = detect_languages(&here);
+    let ask = !matches.is_present("yes") && user_attended();
+    let mut selected = vec![];
+    for (language, count) in &languages {
+        let templates = find_templates(language);
+        if templates.is_empty() {
+            println!("Found {} {} file{} but no tool for it in the catalogue.",
+                     count, language, if *count != 1 { "s" } else { "" });
+            continue;
+        }
+        for template in templates {
+            if !ask || prompt_yes_no(&format!(
+                "Found {} {} file{}.  Add {} ({})?", count, language,
+                if *count != 1 { "s" } else { "" },
+                style(template.id).cyan(), template.description), true)?
+            {
+                selected.push(template);
+            }
+        }
+    }
+
+    let mut config = String::from("tools:\n");
+    for template in &selected {
+        template.write_to(&calm_dir.join(template.id))?;
+        config.push_str(&format!("  {}:\n    include:\n      path: {}\n",
+                                 template.id, template.id));
+    }
+    if selected.is_empty() {
+        config = String::from("tools: {}\n");
+    }
+    fs::create_dir_all(&calm_dir)?;
+    let mut f = fs::File::create(calm_dir.join("calm.yml"))?;
+    f.write_all(config.as_bytes())?;
+
+    println!("Created {} with {} tool{}.", style(".calm/calm.yml").cyan(),
+             selected.len(), if selected.len() != 1 { "s" } else { "" });
+
+    if matches.is_present("hook") {
+        HookManager::new()?.install_hooks()?;
+        println!("Enabled hooks.");
+    }
+    if !selected.is_empty() {
+        println!("Run `calm update` to install the tools.");
+    }
+    Ok(())
+}
+
+const BASH_COMPLETION_HOOK: &'static str = r#"
+_calm_dynamic() {
+    local cur prev
+    cur="${COMP_WORDS[COMP_CWORD]}"
+    prev="${COMP_WORDS[COMP_CWORD-1]}"
+    if [[ "$prev" == "--tool" || "$prev" == "--skip" ]]; then
+        COMPREPLY=( $(compgen -W "$(calm __complete tools 2>/dev/null)" -- "$cur") )
+        return 0
+    fi
+    if [[ $COMP_CWORD -eq 2 && "${COMP_WORDS[1]}" == "run" ]]; then
+        COMPREPLY=( $(compgen -W "$(calm __complete tasks 2>/dev/null)" -- "$cur") )
+        return 0
+    fi
+    _calm "$@"
+}
+
+complete -F _calm_dynamic -o bashdefault -o default calm
+"#;
+
+const ZSH_COMPLETION_HOOK: &'static str = r#"
+_calm_dynamic() {
+    if [[ "${words[CURRENT-1]}" == "--tool" || "${words[CURRENT-1]}" == "--skip" ]]; then
+        compadd -- ${(f)"$(calm __complete tools 2>/dev/null)"}
+        return
+    fi
+    if [[ $CURRENT -eq 3 && "${words[2]}" == "run" ]]; then
+        compadd -- ${(f)"$(calm __complete tasks 2>/dev/null)"}
+        return
+    fi
+    _calm_static "$@"
+}
+
+compdef _calm_dynamic calm
+"#;
+
+const FISH_COMPLETION_HOOK: &'static str = r#"
+complete -c calm -n "__fish_seen_subcommand_from lint format update exec shell env" -l tool -x -a "(calm __complete tools 2>/dev/null)"
+complete -c calm -n "__fish_seen_subcommand_from lint format update exec shell env" -l skip -x -a "(calm __complete tools 2>/dev/null)"
+complete -c calm -n "__fish_seen_subcommand_from run" -x -a "(calm __complete tasks 2>/dev/null)"
+"#;
+
+fn cmd_completions(matches: &ArgMatches) -> Result<()> {
+    let shell = matches.value_of("shell").unwrap();
+    let mut script = vec![];
+    make_app().gen_completions_to("calm", shell.parse().map_err(Error::from)?, &mut script);
+    let script = String::from_utf8_lossy(&script);
+
+    // the static scripts complete flags and subcommands, the hooks ask
+    // calm for the tools and tasks of the current project.  The zsh
+    // script is written for autoloading and calls `_calm` at the end,
+    // so it becomes the body of a function just like an autoloaded one.
+    match shell {
+        "bash" => print!("{}{}", script, BASH_COMPLETION_HOOK),
+        "zsh" => print!("_calm_static() {{\n{}\n}}\n{}", script, ZSH_COMPLETION_HOOK),
+        _ => print!("{}{}", script, FISH_COMPLETION_HOOK),
+    }
+    Ok(())
+}
+
+fn cmd_complete(matches: &ArgMatches) -> Result<()> {
+    // completions never fail, there is just nothing to complete then.
+    // Only the local file is read so nothing is fetched or validated.
+    let section = match matches.value_of("kind").unwrap() {
+        "tools" => "tools",
+        _ => "tasks",
+    };
+    let mut names = match find_config_file().and_then(|x| read_section_keys(&x, section)) {
+        Ok(names) => names,
+        Err(_) => return Ok(()),
+    };
+    if section == "tasks" {
+        names.sort();
+    }
+    for name in names {
+        println!("{}", name);
+    }
+    Ok(())
+}
+
+fn cmd_config_show(ctx: &Context) -> Result<()> {
+    for (key, value, origin) in ctx.config().iter_values_with_origin() {
+        println!("{} = {}  {}", style(key).cyan(), serde_json::to_string(value)?,
+                 style(format!("# {}", origin.map(|x| x.display().to_string())
+                     .unwrap_or_else(|| "<unknown>".to_string()))).dim());
+    }
+    Ok(())
+}
+
+fn cmd_config_check(matches: &ArgMatches) -> Result<()> {
+    let filename = match matches.value_of("path") {
+        Some(path) => PathBuf::from(path),
+        None => find_config_file()?,
+    };
+    let is_tool = filename.file_name().and_then(|x| x.to_str()) == Some("calmtool.yml");
+    let schema = if is_tool { tool_config_schema() } else { config_schema() };
+
+    let errors = validate_file(&schema, &filename)?;
+    for err in &errors {
+        println!("{}:{}", style(filename.display()).cyan(), err);
+    }
+    if !errors.is_empty() {
+        return Err(ErrorKind::QuietExit(1).into());
+    }
+
+    // loading validates extended configs and included tools too
+    if !is_tool {
+        if let Err(err) = Config::from_file(&filename) {
+            print_error(&err);
+            return Err(ErrorKind::QuietExit(1).into());
+        }
+    }
+
+    println!("{} is valid.", style(filename.display()).cyan());
+    Ok(())
+}
+
+fn cmd_config_schema(matches: &ArgMatches) -> Result<()> {
+    let schema = if matches.is_present("tool") {
+        tool_config_schema()
+    } else {
+        config_schema()
+    };
+    let mut doc = schema.to_json_schema();
+    if let Some(obj) = doc.as_object_mut() {
+        obj.insert("$schema".into(), "http://json-schema.org/draft-07/schema#".into());
+    }
+    println!("{}", serde_json::to_string_pretty(&doc)?);
+    Ok(())
+}
+
+fn cmd_lint(workspace: &Workspace, matches: &ArgMatches) -> Result<()> {
     let all = matches.is_present("all");
     let format = matches.value_of("fmt").unwrap_or("human");
     let changed_files;
@@ -137,8 +621,8 @@ fn cmd_lint(ctx: &Context, matches: &ArgMatches) -> Result<()> {
             .map(|values| values.map(|x| Path::new(x)).collect::<Vec<_>>());
     }
 
-    let report = ctx.lint(paths.as_ref().map(|x| &x[..]))?;
-    ctx.clear_log();
+    let report = workspace.lint(paths.as_ref().map(|x| &x[..]))?;
+    workspace.clear_log();
     report.print(format.parse().unwrap())?;
     if report.did_fail() {
         Err(Error::from(ErrorKind::QuietExit(1)))
@@ -147,7 +631,7 @@ fn cmd_lint(ctx: &Context, matches: &ArgMatches) -> Result<()> {
     }
 }
 
-fn cmd_lint_watch(ctx: &Context, matches: &ArgMatches) -> Result<()> {
+fn cmd_lint_watch(workspace: &Workspace, matches: &ArgMatches) -> Result<()> {
     let all = matches.is_present("all");
     let format = matches.value_of("fmt").unwrap_or("human-extended");
 
@@ -158,16 +642,16 @@ fn cmd_lint_watch(ctx: &Context, matches: &ArgMatches) -> Result<()> {
     clear_term();
     println_stderr!("Linting on changes ...");
     let fmt = format.parse().unwrap();
-    watch_files(ctx.base_dir(), &|path: &Path| -> Result<()> {
-        if ctx.is_lintable_file(path)? {
+    watch_files(workspace.root(), &|path: &Path| -> Result<()> {
+        if workspace.is_lintable_file(path)? {
             clear_term();
             println_stderr!("Detected change in {}", style(path.display()).cyan());
             let report = if all {
-                ctx.lint(None)
+                workspace.lint(None)
             } else {
-                ctx.lint(Some(&[path][..]))
+                workspace.lint(Some(&[path][..]))
             }?;
-            ctx.clear_log();
+            workspace.clear_log();
             clear_term();
             if !all {
                 println_stderr!("Results for {}:", style(path.display()).cyan());
@@ -181,7 +665,7 @@ fn cmd_lint_watch(ctx: &Context, matches: &ArgMatches) -> Result<()> {
     unreachable!();
 }
 
-fn cmd_format(ctx: &Context, matches: &ArgMatches) -> Result<()> {
+fn cmd_format(workspace: &Workspace, matches: &ArgMatches) -> Result<()> {
     let changed_files;
     let paths: Vec<&Path>;
 
@@ -197,8 +681,8 @@ fn cmd_format(ctx: &Context, matches: &ArgMatches) -> Result<()> {
         return Ok(());
     }
 
-    let rv = ctx.format(&paths)?;
-    ctx.clear_log();
+    let rv = workspace.format(&paths)?;
+    workspace.clear_log();
     if matches.is_present("write") {
         rv.apply()?;
     } else {
@@ -207,7 +691,7 @@ fn cmd_format(ctx: &Context, matches: &ArgMatches) -> Result<()> {
     Ok(())
 }
 
-fn cmd_hook(ctx: &Context, matches: &ArgMatches) -> Result<()> {
+fn cmd_hook(workspace: &Workspace, matches: &ArgMatches) -> Result<()> {
     let mgr = HookManager::new()?;
     if matches.is_present("install") {
         mgr.install_hooks()?;
@@ -224,11 +708,11 @@ fn cmd_hook(ctx: &Context, matches: &ArgMatches) -> Result<()> {
         let paths: Vec<_> = changed_files.iter().map(|x| x.as_path()).collect();
 
         // format
-        ctx.format(&paths)?.apply()?;
+        workspace.format(&paths)?.apply()?;
 
         // lint
-        let report = ctx.lint(Some(&paths[..]))?;
-        ctx.clear_log();
+        let report = workspace.lint(Some(&paths[..]))?;
+        workspace.clear_log();
         report.print(Format::Human)?;
         if report.did_fail() {
             return Err(Error::from(ErrorKind::QuietExit(1)));
@@ -245,6 +729,181 @@ fn cmd_hook(ctx: &Context, matches: &ArgMatches) -> Result<()> {
     Ok(())
 }
 
+fn cmd_tools(ctx: &Context, matches: &ArgMatches) -> Result<()> {
+    let mut infos = vec![];
+    for tool_id in ctx.active_tools() {
+        infos.push(ctx.create_tool(&tool_id)?.describe()?);
+    }
+
+    if matches.value_of("fmt") == Some("json") {
+        println!("{}", serde_json::to_string_pretty(&infos)?);
+        return Ok(());
+    }
+
+    if infos.is_empty() {
+        println!("No tools configured.");
+    }
+    for (idx, info) in infos.iter().enumerate() {
+        if idx > 0 {
+            println!("");
+        }
+        let status = if info.up_to_date {
+            style("up to date").green()
+        } else if info.installed {
+            style("outdated").yellow()
+        } else {
+            style("not installed").red()
+        };
+        println!("{} ({})", style(&info.id).cyan().bold(), status);
+        if let Some(ref description) = info.description {
+            println!("  {}", description);
+        }
+        if let Some(ref source) = info.source {
+            match info.commit {
+                Some(ref commit) => println!("  source: {} ({})", source, commit),
+                None => println!("  source: {}", source),
+            }
+        }
+        if !info.depends_on.is_empty() {
+            println!("  depends on: {}", info.depends_on.join(", "));
+        }
+        for rt in &info.runtimes {
+            let packages: Vec<_> = rt.packages.iter()
+                .map(|(name, version)| format!("{} {}", name, version))
+                .collect();
+            println!("  runtime: {}{}{}", rt.ty,
+                     rt.flavor.as_ref().map(|x| format!(" ({})", x)).unwrap_or_default(),
+                     if packages.is_empty() { "".into() }
+                     else { format!(": {}", packages.join(", ")) });
+        }
+        if let Some(ref patterns) = info.lint_patterns {
+            println!("  lints: {}", patterns.join(" "));
+        }
+        if let Some(ref patterns) = info.format_patterns {
+            println!("  formats: {}", patterns.join(" "));
+        }
+    }
+    Ok(())
+}
+
+fn cmd_explain(workspace: &Workspace, matches: &ArgMatches) -> Result<()> {
+    let rule = matches.value_of("rule").unwrap();
+    let mut pieces = rule.splitn(2, ':');
+    let (tool_id, code) = match (pieces.next(), pieces.next()) {
+        (Some(tool_id), Some(code)) if !code.is_empty() => (tool_id, code),
+        _ => { fail!("expected a rule like flake8:E501, got '{}'", rule); }
+    };
+
+    let ctx = match workspace.contexts().iter()
+        .find(|x| x.config().get_tool_spec(tool_id).is_some())
+    {
+        Some(ctx) => ctx,
+        None => { fail!("unknown tool '{}'", tool_id); }
+    };
+    let tool = ctx.create_tool(tool_id)?;
+    if !tool.has_rule_docs() {
+        fail!("tool '{}' does not document its rules", tool_id);
+    }
+
+    println!("{}", style(rule).magenta().bold());
+    match tool.rule_description(code)? {
+        Some(description) => println!("\n{}", description.trim_right()),
+        None => println!("\nNo description available."),
+    }
+    if let Some(url) = tool.rule_url(code) {
+        println!("\n{}", style(url).cyan());
+    }
+    Ok(())
+}
+
+fn cmd_doctor(workspace: &Workspace) -> Result<()> {
+    let mut failed = false;
+    for ctx in workspace.contexts() {
+        let problems = diagnose(ctx)?;
+        if problems.is_empty() {
+            println!("{} {}", style(ctx.config().filename().display()).cyan(),
+                     style("is healthy").green());
+            continue;
+        }
+        failed = true;
+        println!("{} has {} problem{}:", style(ctx.config().filename().display()).cyan(),
+                 problems.len(), if problems.len() != 1 { "s" } else { "" });
+        for problem in &problems {
+            println!("- {}", problem);
+        }
+    }
+    if failed {
+        Err(ErrorKind::QuietExit(1).into())
+    } else {
+        Ok(())
+    }
+}
+
+fn cmd_run(ctx: &mut Context, matches: &ArgMatches) -> Result<()> {
+    let name = match matches.value_of("task") {
+        Some(name) => name,
+        None => {
+            let tasks = ctx.config().iter_tasks();
+            if tasks.is_empty() {
+                println!("No tasks configured.");
+            }
+            for (name, task) in tasks {
+                match task.description {
+                    Some(ref description) => {
+                        println!("{}  {}", style(name).cyan(), description);
+                    }
+                    None => println!("{}", style(name).cyan()),
+                }
+            }
+            return Ok(());
+        }
+    };
+
+    ctx.ensure_toolchains()?;
+    let args: Vec<&str> = matches.values_of("args")
+        .map(|x| x.collect())
+        .unwrap_or_else(Vec::new);
+    ctx.run_task(name, &args)
+}
+
+fn run_in_environment(ctx: &mut Context, mut cmd: CommandBuilder) -> Result<()> {
+    ctx.ensure_toolchains()?;
+    ctx.environment()?.configure(&mut cmd);
+    ctx.clear_log();
+    let status = cmd.run_attached()?;
+    if !status.success() {
+        return Err(ErrorKind::QuietExit(status.code().unwrap_or(1)).into());
+    }
+    Ok(())
+}
+
+fn cmd_exec(ctx: &mut Context, matches: &ArgMatches) -> Result<()> {
+    let args: Vec<&str> = matches.values_of("cmd").unwrap().collect();
+    let mut cmd = CommandBuilder::new(args[0]);
+    for arg in &args[1..] {
+        cmd.arg(arg);
+    }
+    // tool steps run in the project folder too
+    cmd.current_dir(ctx.base_dir());
+    run_in_environment(ctx, cmd)
+}
+
+fn cmd_env(ctx: &mut Context, matches: &ArgMatches) -> Result<()> {
+    let format: EnvFormat = matches.value_of("fmt").unwrap_or("sh").parse()?;
+    ctx.ensure_toolchains()?;
+    let env = ctx.environment()?;
+    ctx.clear_log();
+    print!("{}", env.export(format)?);
+    Ok(())
+}
+
+fn cmd_shell(ctx: &mut Context) -> Result<()> {
+    let shell = env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
+    let mut cmd = CommandBuilder::new(&shell);
+    cmd.env("CALM_SHELL", "1");
+    run_in_environment(ctx, cmd)
+}
+
 fn cmd_which(ctx: &Context, matches: &ArgMatches) -> Result<()> {
     let cmd = matches.value_of("cmd").unwrap();
     if let Some(path) = ctx.find_command(cmd)? {
@@ -256,7 +915,7 @@ fn cmd_which(ctx: &Context, matches: &ArgMatches) -> Result<()> {
 }
 
 fn run() -> Result<()> {
-    execute(env::args().collect(), Config::from_env()?)
+    execute(env::args().collect())
 }
 
 /// Helper that renders an error to stderr.
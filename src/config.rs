@@ -1,15 +1,21 @@
 use std::fs;
 use std::env;
+use std::fmt;
 use std::borrow::Cow;
 use std::path::{Path, PathBuf};
-use std::collections::HashMap;
-use std::collections::hash_map::Keys as HashMapKeys;
+use std::collections::{BTreeMap, HashMap};
+use std::slice::Iter as SliceIter;
 
 use prelude::*;
 use utils::serde::{Pattern, LinkSpec};
+use utils::archive::get_cached_file_sha256;
+use utils::git::checkout_rev;
+use schema::{config_schema, tool_config_schema, ensure_valid_file};
 
 use sha1::Sha1;
+use strsim::levenshtein;
 use serde_yaml;
+use serde_yaml::{Mapping, Value};
 
 #[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum ReportPatternMatch {
@@ -18,6 +24,7 @@ pub enum ReportPatternMatch {
 }
 
 #[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct ParseLinesAction {
     pub pattern: Pattern,
     #[serde(rename="report-match")]
@@ -25,6 +32,7 @@ pub struct ParseLinesAction {
 }
 
 #[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct StreamActions {
     #[serde(rename="parse-lines")]
     pub parse_lines: Option<ParseLinesAction>,
@@ -40,33 +48,50 @@ pub enum ToolCommand {
 }
 
 #[derive(Deserialize, Debug, Clone)]
-#[serde(untagged)]
+#[serde(untagged, deny_unknown_fields)]
 pub enum ToolStep {
     Command {
         description: Option<String>,
         cmd: ToolCommand,
         stdout: Option<StreamActions>,
         stderr: Option<StreamActions>,
+        #[serde(skip)]
+        tool_dir: Option<PathBuf>,
     },
     Link {
         description: Option<String>,
         link: LinkSpec,
+        #[serde(skip)]
+        tool_dir: Option<PathBuf>,
     }
 }
 
 #[derive(Deserialize, Default, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct LintSpec {
     pub patterns: Vec<Pattern>,
     pub run: Vec<ToolStep>,
 }
 
 #[derive(Deserialize, Default, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct FormatSpec {
     pub patterns: Vec<Pattern>,
     pub run: Vec<ToolStep>,
 }
 
+/// Where the documentation of the rules of a tool lives.
 #[derive(Deserialize, Default, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DocsSpec {
+    /// a url template with `${code}` for the rule code.
+    pub url: Option<String>,
+    /// a folder with a `<code>.md` or `<code>.txt` file per rule.
+    pub dir: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct RuntimeConfig {
     /// some runtimes have different flavors that can be selected.
     flavor: Option<String>,
@@ -75,50 +100,189 @@ pub struct RuntimeConfig {
     packages: HashMap<String, String>,
 }
 
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamType {
+    #[serde(rename="string")]
+    String,
+    #[serde(rename="int")]
+    Int,
+    #[serde(rename="bool")]
+    Bool,
+}
+
+impl Default for ParamType {
+    fn default() -> ParamType {
+        ParamType::String
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
-#[serde(untagged)]
+#[serde(deny_unknown_fields)]
+pub struct ParamSpec {
+    #[serde(rename="type", default)]
+    pub ty: ParamType,
+    pub default: Option<Value>,
+    pub description: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged, deny_unknown_fields)]
 pub enum RemoteToolInclude {
     Git {
         git: String,
         rev: Option<String>,
         path: Option<String>,
+        #[serde(rename="with", default)]
+        with: HashMap<String, Value>,
+    },
+    Archive {
+        archive: String,
+        sha256: Option<String>,
+        path: Option<String>,
+        #[serde(rename="with", default)]
+        with: HashMap<String, Value>,
     },
     Path {
         path: PathBuf,
+        prefix: Option<String>,
+        #[serde(rename="with", default)]
+        with: HashMap<String, Value>,
     }
 }
 
+/// A tool that another tool's calmtool.yml extends.
+#[derive(Debug, Clone)]
+pub struct ToolBase {
+    pub include: RemoteToolInclude,
+    /// the folder the include is relative to.
+    pub config_dir: PathBuf,
+    pub tool_dir_base: PathBuf,
+}
+
 #[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct ToolSpec {
     #[serde(skip)]
     pub tool_dir_base: Option<PathBuf>,
     pub include: Option<RemoteToolInclude>,
+    /// files the tool should never touch.
+    #[serde(default)]
+    pub exclude: Vec<Pattern>,
+    /// another tool this calmtool.yml builds on.
+    pub extends: Option<RemoteToolInclude>,
+    /// all tools this tool extends directly or indirectly.
+    #[serde(skip)]
+    pub bases: Vec<ToolBase>,
     pub description: Option<String>,
+    /// tools that have to run before this one.
+    #[serde(rename="depends-on", default)]
+    pub depends_on: Vec<String>,
+    /// the parameters the tool can be customized with.
+    #[serde(default)]
+    pub params: HashMap<String, ParamSpec>,
+    /// the resolved values of all parameters.
+    #[serde(skip)]
+    pub param_values: HashMap<String, String>,
     #[serde(default)]
     pub runtimes: HashMap<String, RuntimeConfig>,
     #[serde(rename="install", default)]
     pub install_steps: Vec<ToolStep>,
     pub lint: Option<LintSpec>,
     pub format: Option<FormatSpec>,
+    pub docs: Option<DocsSpec>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct StandaloneToolConfig {
     tool: ToolSpec,
 }
 
 #[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Rule {
     patterns: Vec<String>,
     run: String,
 }
 
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct OfflineConfig {
+    /// folders with wheels and sdists pip can install from offline.
+    #[serde(default)]
+    wheelhouses: Vec<PathBuf>,
+}
+
+/// A project script made of tool steps.
 #[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TaskSpec {
+    pub description: Option<String>,
+    /// the tool whose runtimes the task runs with.
+    pub tool: Option<String>,
+    pub run: Vec<ToolStep>,
+}
+
+/// A named subset of the tools with optional overrides.
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileConfig {
+    tools: Option<Vec<String>>,
+    #[serde(rename="override")]
+    overrides: Option<Value>,
+}
+
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct HookConfig {
+    profile: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct ConfigValues {
     #[serde(default)]
     tools: HashMap<String, ToolSpec>,
     #[serde(default)]
+    tasks: HashMap<String, TaskSpec>,
+    #[serde(default)]
     rules: Vec<Rule>,
+    #[serde(rename="auto-update", default)]
+    auto_update: bool,
+    #[serde(default)]
+    offline: OfflineConfig,
+    #[serde(default)]
+    exclude: Vec<Pattern>,
+    #[serde(rename="respect-gitignore", default="default_respect_gitignore")]
+    respect_gitignore: bool,
+    #[serde(default)]
+    profiles: HashMap<String, ProfileConfig>,
+    #[serde(default)]
+    hooks: HashMap<String, HookConfig>,
+}
+
+fn default_respect_gitignore() -> bool {
+    true
+}
+
+/// The config a calm.yml extends.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged, deny_unknown_fields)]
+enum ConfigBase {
+    Path(PathBuf),
+    Git {
+        git: String,
+        rev: Option<String>,
+        path: Option<String>,
+    },
+}
+
+/// A git checkout of a config that is extended.
+#[derive(Debug, Clone)]
+pub struct GitConfigBase {
+    git: String,
+    rev: Option<String>,
+    checkout_dir: PathBuf,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -126,10 +290,19 @@ pub struct Config {
     filename: PathBuf,
     config_dir: PathBuf,
     cache_dir: PathBuf,
+    store_dir: PathBuf,
+    project_key: String,
+    profile: Option<String>,
+    offline: bool,
     values: ConfigValues,
+    /// the tool ids in declaration and dependency order.
+    tool_order: Vec<String>,
+    merged_values: Value,
+    origins: BTreeMap<String, PathBuf>,
+    git_bases: Vec<GitConfigBase>,
 }
 
-fn find_config_file() -> Result<PathBuf> {
+pub fn find_config_file() -> Result<PathBuf> {
     if let Ok(mut path) = env::current_dir() {
         loop {
             path.push(".calm/calm.yml");
@@ -188,6 +361,26 @@ impl ToolStep {
         }
     }
 
+    /// The folder of the tool that declared the step if it does not
+    /// belong to the tool running it.
+    pub fn tool_dir(&self) -> Option<&Path> {
+        match *self {
+            ToolStep::Command { ref tool_dir, .. } |
+            ToolStep::Link { ref tool_dir, .. } => tool_dir.as_ref().map(|x| x.as_path()),
+        }
+    }
+
+    fn set_default_tool_dir(&mut self, dir: &Path) {
+        match *self {
+            ToolStep::Command { ref mut tool_dir, .. } |
+            ToolStep::Link { ref mut tool_dir, .. } => {
+                if tool_dir.is_none() {
+                    *tool_dir = Some(dir.to_path_buf());
+                }
+            }
+        }
+    }
+
     pub fn cmd(&self) -> Option<&ToolCommand> {
         match *self {
             ToolStep::Command { ref cmd, .. } => Some(cmd),
@@ -217,73 +410,634 @@ impl ToolStep {
     }
 }
 
-fn merge_tool_config(tool: &mut ToolSpec, config_dir: &Path, cache_dir: &Path) -> Result<()> {
-    tool.tool_dir_base = Some(tool.include
-        .as_ref().unwrap().local_path_reference(config_dir, cache_dir));
-    let mut tool_config = tool.tool_dir_base.as_ref().unwrap().to_path_buf();
-    if let Some(prefix) = tool.tool_dir_prefix() {
-        tool_config.push(prefix);
+fn merge_tool_spec(tool: &mut ToolSpec, other: ToolSpec) {
+    if let Some(val) = other.description {
+        tool.description = Some(val);
+    }
+    for (name, param) in other.params.into_iter() {
+        tool.params.insert(name, param);
+    }
+    tool.exclude.extend(other.exclude);
+    for (id, rtc) in other.runtimes.into_iter() {
+        tool.runtimes.insert(id, rtc);
+    }
+    for ts in other.install_steps.into_iter() {
+        tool.install_steps.push(ts);
+    }
+    if let Some(val) = other.lint {
+        tool.lint = Some(val);
+    }
+    if let Some(val) = other.format {
+        tool.format = Some(val);
+    }
+    if let Some(val) = other.docs {
+        tool.docs = Some(val);
+    }
+}
+
+/// Loads the calmtool.yml of an include together with everything it
+/// extends.  `chain` holds the tool folders that are currently being
+/// loaded to detect cycles.
+fn load_tool_include(rti: &RemoteToolInclude, config_dir: &Path, cache_dir: &Path,
+                     chain: &mut Vec<PathBuf>, bases: &mut Vec<ToolBase>)
+    -> Result<Option<ToolSpec>>
+{
+    let mut tool_dir = rti.local_path_reference(config_dir, cache_dir);
+    if let Some(prefix) = rti.path_prefix() {
+        tool_dir.push(prefix);
+    }
+
+    if chain.contains(&tool_dir) {
+        let mut names: Vec<_> = chain.iter().map(|x| x.display().to_string()).collect();
+        names.push(tool_dir.display().to_string());
+        fail!("include cycle detected: {}", names.join(" -> "));
+    }
+
+    let tool_config = tool_dir.join("calmtool.yml");
+    if fs::metadata(&tool_config).is_err() {
+        return Ok(None);
+    }
+    ensure_valid_file(&tool_config_schema(), &tool_config)?;
+    let mut f = fs::File::open(&tool_config)?;
+    let rt: StandaloneToolConfig = serde_yaml::from_reader(&mut f)
+        .chain_err(|| format!("Failed to parse {}", tool_config.display()))?;
+    let mut spec = rt.tool;
+
+    for step in spec.install_steps.iter_mut()
+        .chain(spec.lint.iter_mut().flat_map(|x| x.run.iter_mut()))
+        .chain(spec.format.iter_mut().flat_map(|x| x.run.iter_mut()))
+    {
+        step.set_default_tool_dir(&tool_dir);
+    }
+    if let Some(ref mut docs) = spec.docs {
+        docs.dir = docs.dir.take().map(|x| tool_dir.join(x));
     }
-    tool_config.push("calmtool.yml");
 
-    if fs::metadata(&tool_config).is_ok() {
-        let mut f = fs::File::open(&tool_config)?;
-        let rt: StandaloneToolConfig = serde_yaml::from_reader(&mut f)
-            .chain_err(|| "Failed to parse calmtool.yml")?;
+    if let Some(base_rti) = spec.extends.take() {
+        bases.push(ToolBase {
+            include: base_rti.clone(),
+            config_dir: tool_dir.clone(),
+            tool_dir_base: base_rti.local_path_reference(&tool_dir, cache_dir),
+        });
+        chain.push(tool_dir.clone());
+        let base = load_tool_include(&base_rti, &tool_dir, cache_dir, chain, bases)?;
+        chain.pop();
 
-        if let Some(val) = rt.tool.description {
-            tool.description = Some(val);
+        if let Some(mut base) = base {
+            for (name, value) in base_rti.with() {
+                match base.params.get_mut(name) {
+                    Some(param) => { param.default = Some(value.clone()); }
+                    None => {
+                        fail!("{} extends a tool without parameter '{}'",
+                              tool_config.display(), name);
+                    }
+                }
+            }
+            merge_tool_spec(&mut base, spec);
+            spec = base;
         }
-        for (id, rtc) in rt.tool.runtimes.into_iter() {
-            tool.runtimes.insert(id, rtc);
+    }
+
+    Ok(Some(spec))
+}
+
+fn merge_tool_config(tool: &mut ToolSpec, config_dir: &Path, cache_dir: &Path) -> Result<()> {
+    let rti = tool.include.clone().unwrap();
+    tool.tool_dir_base = Some(rti.local_path_reference(config_dir, cache_dir));
+
+    let mut bases = vec![];
+    if let Some(spec) = load_tool_include(&rti, config_dir, cache_dir,
+                                          &mut vec![], &mut bases)? {
+        merge_tool_spec(tool, spec);
+    }
+    tool.bases = bases;
+
+    Ok(())
+}
+
+fn format_param_value(tool_id: &str, name: &str, ty: ParamType,
+                      value: &Value) -> Result<String> {
+    match (ty, value) {
+        (ParamType::String, &Value::String(ref val)) => Ok(val.to_string()),
+        (ParamType::String, &Value::Number(ref val)) => Ok(val.to_string()),
+        (ParamType::Int, &Value::Number(ref val)) if val.as_i64().is_some() => {
+            Ok(val.to_string())
         }
-        for ts in rt.tool.install_steps.into_iter() {
-            tool.install_steps.push(ts);
+        (ParamType::Bool, &Value::Bool(val)) => {
+            Ok(if val { "true" } else { "false" }.to_string())
         }
-        if let Some(val) = rt.tool.lint {
-            tool.lint = Some(val);
+        _ => {
+            fail!("parameter '{}' of tool '{}' must be of type {}", name, tool_id,
+                  match ty {
+                      ParamType::String => "string",
+                      ParamType::Int => "int",
+                      ParamType::Bool => "bool",
+                  });
         }
-        if let Some(val) = rt.tool.format {
-            tool.format = Some(val);
+    }
+}
+
+/// Resolves the parameters of a tool from the `with` values of the
+/// include and the defaults declared by the tool.
+fn resolve_tool_params(tool_id: &str, tool: &mut ToolSpec) -> Result<()> {
+    let empty = HashMap::new();
+    let with = tool.include.as_ref().map(|x| x.with()).unwrap_or(&empty);
+
+    for name in with.keys() {
+        if !tool.params.contains_key(name) {
+            let mut known: Vec<_> = tool.params.keys().map(|x| x.as_str()).collect();
+            known.sort();
+            fail!("tool '{}' has no parameter '{}' (known parameters: {})",
+                  tool_id, name, if known.is_empty() {
+                      "none".to_string()
+                  } else {
+                      known.join(", ")
+                  });
         }
     }
 
+    let mut values = HashMap::new();
+    for (name, param) in tool.params.iter() {
+        let value = match with.get(name).or(param.default.as_ref()) {
+            Some(value) => value,
+            None => {
+                fail!("tool '{}' requires parameter '{}'", tool_id, name);
+            }
+        };
+        values.insert(name.to_string(),
+                      format_param_value(tool_id, name, param.ty, value)?);
+    }
+
+    tool.param_values = values;
     Ok(())
 }
 
+/// Returns the folder that holds the caches of all projects.
+pub fn get_env_cache_root() -> Result<PathBuf> {
+    let mut rv = env::home_dir().ok_or(
+        Error::from("could not find home folder"))?;
+    rv.push(".calm");
+    rv.push("env-cache");
+    Ok(rv)
+}
+
+/// Returns the folder of the store that is shared by all projects.
+pub fn get_store_dir() -> Result<PathBuf> {
+    let mut rv = env::home_dir().ok_or(
+        Error::from("could not find home folder"))?;
+    rv.push(".calm");
+    rv.push("store");
+    Ok(rv)
+}
+
+/// Reads the keys of a section of a config file without validating it
+/// or resolving extends and includes.  This is cheap enough for shell
+/// completions.
+pub fn read_section_keys(filename: &Path, section: &str) -> Result<Vec<String>> {
+    let mut f = fs::File::open(filename)?;
+    let value: Value = serde_yaml::from_reader(&mut f)?;
+    if_chain! {
+        if let Value::Mapping(ref map) = value;
+        if let Some(&Value::Mapping(ref items)) = map.get(&Value::String(section.into()));
+        then {
+            Ok(items.iter().filter_map(|(key, _)| key.as_str())
+               .map(|x| x.to_string())
+               .collect())
+        } else {
+            Ok(vec![])
+        }
+    }
+}
+
+/// Checks if offline mode is enabled through `CALM_OFFLINE`.
+pub fn is_offline_env() -> bool {
+    env::var("CALM_OFFLINE").map(|x| x != "" && x != "0").unwrap_or(false)
+}
+
+fn join_key(prefix: &str, key: &Value) -> String {
+    let key = match *key {
+        Value::String(ref key) => key.to_string(),
+        ref other => serde_yaml::to_string(other).unwrap_or_default(),
+    };
+    if prefix.is_empty() {
+        key
+    } else {
+        format!("{}.{}", prefix, key)
+    }
+}
+
+fn clear_origins(origins: &mut BTreeMap<String, PathBuf>, key: &str) {
+    let nested = format!("{}.", key);
+    let stale: Vec<String> = origins.keys()
+        .filter(|x| x.as_str() == key || x.starts_with(&nested))
+        .cloned()
+        .collect();
+    for key in stale {
+        origins.remove(&key);
+    }
+}
+
+/// Merges a config layer over the values of the configs it extends.
+/// Mappings are merged key by key, a `null` removes a key and all other
+/// values replace the inherited ones.
+fn merge_config_value(base: &mut Value, overlay: &Value, key: &str, origin: &Path,
+                      origins: &mut BTreeMap<String, PathBuf>) {
+    if let Value::Mapping(ref overlay_map) = *overlay {
+        let is_mapping = match *base {
+            Value::Mapping(_) => true,
+            _ => false,
+        };
+        if !is_mapping {
+            clear_origins(origins, key);
+            *base = Value::Mapping(Mapping::new());
+        }
+        if let Value::Mapping(ref mut base_map) = *base {
+            for (child, value) in overlay_map.iter() {
+                let child_key = join_key(key, child);
+                if *value == Value::Null {
+                    base_map.remove(child);
+                    clear_origins(origins, &child_key);
+                    continue;
+                }
+                if !base_map.contains_key(child) {
+                    base_map.insert(child.clone(), Value::Null);
+                }
+                merge_config_value(base_map.get_mut(child).unwrap(), value,
+                                   &child_key, origin, origins);
+            }
+        }
+    } else {
+        clear_origins(origins, key);
+        origins.insert(key.to_string(), origin.to_path_buf());
+        *base = overlay.clone();
+    }
+}
+
+fn absolutize_path(value: &mut Value, base_dir: &Path) {
+    let path = match *value {
+        Value::String(ref path) => {
+            if path.starts_with("file://") {
+                format!("file://{}", base_dir.join(&path[7..]).display())
+            } else {
+                base_dir.join(path).display().to_string()
+            }
+        }
+        _ => return,
+    };
+    *value = Value::String(path);
+}
+
+/// Paths in an extended config are relative to that config so they are
+/// made absolute before the config is merged into the one extending it.
+fn absolutize_config_paths(value: &mut Value, base_dir: &Path) {
+    let tools_key = Value::String("tools".into());
+    let include_key = Value::String("include".into());
+    let git_key = Value::String("git".into());
+    let archive_key = Value::String("archive".into());
+    let path_key = Value::String("path".into());
+    let offline_key = Value::String("offline".into());
+    let wheelhouses_key = Value::String("wheelhouses".into());
+
+    let map = match *value {
+        Value::Mapping(ref mut map) => map,
+        _ => return,
+    };
+
+    if let Some(&mut Value::Mapping(ref mut tools)) = map.get_mut(&tools_key) {
+        for (_, tool) in tools.iter_mut() {
+            if_chain! {
+                if let Value::Mapping(ref mut tool) = *tool;
+                if let Some(&mut Value::Mapping(ref mut include)) = tool.get_mut(&include_key);
+                if !include.contains_key(&git_key);
+                then {
+                    let key = if include.contains_key(&archive_key) {
+                        &archive_key
+                    } else {
+                        &path_key
+                    };
+                    if let Some(value) = include.get_mut(key) {
+                        absolutize_path(value, base_dir);
+                    }
+                }
+            }
+        }
+    }
+
+    if_chain! {
+        if let Some(&mut Value::Mapping(ref mut offline)) = map.get_mut(&offline_key);
+        if let Some(&mut Value::Sequence(ref mut wheelhouses)) =
+            offline.get_mut(&wheelhouses_key);
+        then {
+            for value in wheelhouses.iter_mut() {
+                absolutize_path(value, base_dir);
+            }
+        }
+    }
+}
+
+/// Returns the filename of an extended config and checks out git
+/// bases into the cache if they are not available yet.  In offline
+/// mode missing git bases are an error.
+fn resolve_config_base(base: &ConfigBase, config_dir: &Path, cache_dir: &Path,
+                       git_bases: &mut Vec<GitConfigBase>, offline: bool)
+    -> Result<PathBuf>
+{
+    match *base {
+        ConfigBase::Path(ref path) => Ok(config_dir.join(path)),
+        ConfigBase::Git { ref git, ref rev, ref path } => {
+            let mut sha = Sha1::new();
+            sha.update(git.as_bytes());
+            sha.update(b"\x00");
+            if let &Some(ref rev) = rev {
+                sha.update(rev.as_bytes());
+            }
+            let checkout_dir = cache_dir.join("bases").join(sha.digest().to_string());
+            if fs::metadata(&checkout_dir).is_err() {
+                if offline {
+                    fail!("extended config {} is not available offline; run `calm update`",
+                          git);
+                }
+                checkout_rev(&checkout_dir, git, rev.as_ref().map(|x| x.as_str()),
+                             None, false)
+                    .chain_err(|| format!("Could not fetch extended config {}", git))?;
+            }
+            git_bases.push(GitConfigBase {
+                git: git.to_string(),
+                rev: rev.clone(),
+                checkout_dir: checkout_dir.clone(),
+            });
+            Ok(checkout_dir.join(path.as_ref().map(|x| x.as_str())
+                .unwrap_or(".calm/calm.yml")))
+        }
+    }
+}
+
+/// Loads a config file merged over all the configs it extends.
+fn load_config_value(filename: &Path, cache_dir: &Path, chain: &mut Vec<PathBuf>,
+                     origins: &mut BTreeMap<String, PathBuf>,
+                     git_bases: &mut Vec<GitConfigBase>, offline: bool) -> Result<Value> {
+    // `..` in extends would otherwise hide cycles
+    let canonical = filename.canonicalize()
+        .chain_err(|| format!("Could not open {}", filename.display()))?;
+    if chain.iter().any(|x| x == &canonical) {
+        let mut names: Vec<_> = chain.iter().map(|x| x.display().to_string()).collect();
+        names.push(canonical.display().to_string());
+        fail!("config extends itself: {}", names.join(" -> "));
+    }
+
+    let config_dir = filename.parent().unwrap();
+    ensure_valid_file(&config_schema(), filename)?;
+    let mut f = fs::File::open(filename)
+        .chain_err(|| format!("Could not open {}", filename.display()))?;
+    let mut value: Value = serde_yaml::from_reader(&mut f)
+        .chain_err(|| format!("Failed to parse {}", filename.display()))?;
+    if !chain.is_empty() {
+        absolutize_config_paths(&mut value, config_dir);
+    }
+
+    let extends = match value {
+        Value::Mapping(ref mut map) => map.remove(&Value::String("extends".into())),
+        _ => None,
+    };
+
+    let mut rv = Value::Mapping(Mapping::new());
+    if let Some(extends) = extends {
+        let base: ConfigBase = serde_yaml::from_value(extends)
+            .chain_err(|| format!("Invalid extends in {}", filename.display()))?;
+        let base_filename = resolve_config_base(&base, config_dir, cache_dir, git_bases,
+                                                offline)?;
+        chain.push(canonical);
+        rv = load_config_value(&base_filename, cache_dir, chain, origins, git_bases,
+                               offline)?;
+        chain.pop();
+    }
+
+    merge_config_value(&mut rv, &value, "", filename, origins);
+    Ok(rv)
+}
+
+/// Fails with an error about an unknown profile that suggests one of
+/// the configured profiles.
+pub fn fail_unknown_profile(name: &str, names: &[String]) -> Result<()> {
+    if names.is_empty() {
+        fail!("unknown profile '{}' (no profiles are configured)", name);
+    }
+    match names.iter().min_by_key(|x| levenshtein(name, x)) {
+        Some(suggestion) if levenshtein(name, suggestion) <= 3 => {
+            fail!("unknown profile '{}', did you mean '{}'?", name, suggestion);
+        }
+        _ => {
+            fail!("unknown profile '{}' (available: {})", name, names.join(", "));
+        }
+    }
+}
+
+/// Restricts the tools of a config to the ones of a profile and merges
+/// the overrides of the profile into them.  Returns `false` and leaves
+/// the config alone if it does not define the profile.
+fn apply_profile(values: &mut Value, name: &str, filename: &Path,
+                 origins: &mut BTreeMap<String, PathBuf>) -> Result<bool> {
+    let profiles_key = Value::String("profiles".into());
+    let tools_key = Value::String("tools".into());
+
+    let map = match *values {
+        Value::Mapping(ref mut map) => map,
+        _ => return Ok(false),
+    };
+
+    let profile: ProfileConfig = {
+        let profile = match map.get(&profiles_key) {
+            Some(&Value::Mapping(ref profiles)) => profiles.get(&Value::String(name.into())),
+            _ => None,
+        };
+        match profile {
+            Some(profile) => serde_yaml::from_value(profile.clone())
+                .chain_err(|| format!("Invalid profile '{}'", name))?,
+            None => return Ok(false),
+        }
+    };
+
+    if !map.contains_key(&tools_key) {
+        map.insert(tools_key.clone(), Value::Mapping(Mapping::new()));
+    }
+    let tools = map.get_mut(&tools_key).unwrap();
+
+    if let Some(enabled) = profile.tools {
+        if let Value::Mapping(ref mut tools) = *tools {
+            for tool_id in &enabled {
+                if !tools.contains_key(&Value::String(tool_id.to_string())) {
+                    fail!("profile '{}' enables unknown tool '{}'", name, tool_id);
+                }
+            }
+            let disabled: Vec<Value> = tools.iter()
+                .map(|(key, _)| key.clone())
+                .filter(|key| !enabled.iter().any(|x| Some(x.as_str()) == key.as_str()))
+                .collect();
+            for key in disabled {
+                clear_origins(origins, &join_key("tools", &key));
+                tools.remove(&key);
+            }
+        }
+    }
+
+    if let Some(ref overrides) = profile.overrides {
+        merge_config_value(tools, overrides, "tools", filename, origins);
+    }
+
+    Ok(true)
+}
+
+/// Orders the tools by their declaration in the config while making
+/// sure every tool comes after the tools it depends on.
+fn sort_tools(values: &Value, tools: &HashMap<String, ToolSpec>, allow_missing: bool)
+    -> Result<Vec<String>>
+{
+    fn visit(tool_id: &str, tools: &HashMap<String, ToolSpec>, allow_missing: bool,
+             chain: &mut Vec<String>, rv: &mut Vec<String>) -> Result<()> {
+        if rv.iter().any(|x| x == tool_id) {
+            return Ok(());
+        }
+        if let Some(idx) = chain.iter().position(|x| x == tool_id) {
+            let mut names = chain[idx..].to_vec();
+            names.push(tool_id.to_string());
+            fail!("dependency cycle between tools: {}", names.join(" -> "));
+        }
+        chain.push(tool_id.to_string());
+        for dep in &tools[tool_id].depends_on {
+            if tools.contains_key(dep) {
+                visit(dep, tools, allow_missing, chain, rv)?;
+            } else if !allow_missing {
+                fail!("tool '{}' depends on unknown tool '{}'", tool_id, dep);
+            }
+        }
+        chain.pop();
+        rv.push(tool_id.to_string());
+        Ok(())
+    }
+
+    // serde_yaml keeps the order of mappings which the hash map loses
+    let declared: Vec<&str> = match values.get("tools") {
+        Some(&Value::Mapping(ref map)) => {
+            map.iter().filter_map(|(key, _)| key.as_str()).collect()
+        }
+        _ => vec![],
+    };
+
+    let mut rv = vec![];
+    for tool_id in declared {
+        if tools.contains_key(tool_id) {
+            visit(tool_id, tools, allow_missing, &mut vec![], &mut rv)?;
+        }
+    }
+    Ok(rv)
+}
+
+fn add_config_leaves<'a>(value: &'a Value, key: &str,
+                         rv: &mut Vec<(String, &'a Value)>) {
+    match *value {
+        Value::Mapping(ref map) => {
+            for (child, value) in map.iter() {
+                add_config_leaves(value, &join_key(key, child), rv);
+            }
+        }
+        ref value => rv.push((key.to_string(), value)),
+    }
+}
+
 impl Config {
-    pub fn from_env() -> Result<Config> {
-        let filename = find_config_file()?;
-        let config_dir = filename.parent().unwrap().to_path_buf();
+    pub fn from_file(filename: &Path) -> Result<Config> {
+        Config::load(filename, None, is_offline_env())
+    }
 
-        let mut f = fs::File::open(&filename)
-            .chain_err(|| "Could not open .calm/calm.yml")?;
-        let mut rv: ConfigValues = serde_yaml::from_reader(&mut f)
-            .chain_err(|| "Failed to parse .calm/calm.yml")?;
+    /// Loads a config with the tools of a profile only.  If the config
+    /// does not define the profile all tools are loaded and `profile()`
+    /// returns `None`.  In offline mode extended git configs are not
+    /// fetched.
+    pub fn load(filename: &Path, profile: Option<&str>, offline: bool) -> Result<Config> {
+        let filename = filename.to_path_buf();
+        let config_dir = filename.parent().unwrap().to_path_buf();
 
         let mut sha = Sha1::new();
         sha.update(filename.to_string_lossy().as_bytes());
-        let mut cache_dir = env::home_dir().ok_or(
-            Error::from("could not find home folder"))?;
-        cache_dir.push(".calm");
-        cache_dir.push("env-cache");
-        cache_dir.push(sha.digest().to_string());
+        let project_key = sha.digest().to_string();
+        let cache_dir = get_env_cache_root()?.join(&project_key);
+
+        let mut origins = BTreeMap::new();
+        let mut git_bases = vec![];
+        let mut merged_values = load_config_value(&filename, &cache_dir, &mut vec![],
+                                                  &mut origins, &mut git_bases, offline)?;
+        // configs that do not define the profile keep all their tools
+        let profile = match profile {
+            Some(name) if apply_profile(&mut merged_values, name, &filename,
+                                        &mut origins)? => Some(name),
+            _ => None,
+        };
+        let mut rv: ConfigValues = serde_yaml::from_value(merged_values.clone())
+            .chain_err(|| "Failed to parse .calm/calm.yml")?;
 
         // resolve includes and fail silently
-        for mut tool in rv.tools.values_mut() {
+        for (tool_id, mut tool) in rv.tools.iter_mut() {
             if tool.include.is_some() {
-                merge_tool_config(&mut tool, &config_dir, &cache_dir)?;
+                merge_tool_config(&mut tool, &config_dir, &cache_dir)
+                    .chain_err(|| format!("Failed to load include of tool '{}'", tool_id))?;
             }
+            resolve_tool_params(tool_id, &mut tool)?;
         }
+        // a profile may leave out tools others depend on
+        let tool_order = sort_tools(&merged_values, &rv.tools, profile.is_some())?;
 
         Ok(Config {
             filename: filename,
             config_dir: config_dir,
             cache_dir: cache_dir,
+            store_dir: get_store_dir()?,
+            project_key: project_key,
+            profile: profile.map(|x| x.to_string()),
+            offline: offline,
             values: rv,
+            tool_order: tool_order,
+            merged_values: merged_values,
+            origins: origins,
+            git_bases: git_bases,
         })
     }
 
+    /// Loads the config again from disk with the same profile.
+    pub fn reload(&self) -> Result<Config> {
+        Config::load(&self.filename, self.profile(), self.offline)
+    }
+
+    /// Fetches the latest revisions of all extended git configs.  The
+    /// config has to be loaded again afterwards.
+    pub fn update_bases(&self) -> Result<()> {
+        for base in &self.git_bases {
+            checkout_rev(&base.checkout_dir, &base.git,
+                         base.rev.as_ref().map(|x| x.as_str()), None, false)
+                .chain_err(|| format!("Could not fetch extended config {}", base.git))?;
+        }
+        Ok(())
+    }
+
+    /// Returns all values of the merged config together with the
+    /// file they came from.
+    pub fn iter_values_with_origin(&self) -> Vec<(String, &Value, Option<&Path>)> {
+        let mut leaves = vec![];
+        add_config_leaves(&self.merged_values, "", &mut leaves);
+        leaves.into_iter().map(|(key, value)| {
+            let origin = self.origins.get(&key).map(|x| x.as_path());
+            (key, value, origin)
+        }).collect()
+    }
+
+    pub fn filename(&self) -> &Path {
+        &self.filename
+    }
+
+    /// The lock file records the resolved commits of git includes.
+    pub fn lock_filename(&self) -> PathBuf {
+        self.config_dir.join("calm.lock")
+    }
+
     pub fn config_dir(&self) -> &Path {
         &self.config_dir
     }
@@ -292,13 +1046,92 @@ impl Config {
         &self.cache_dir
     }
 
-    pub fn iter_tools(&self) -> HashMapKeys<String, ToolSpec> {
-        self.values.tools.keys()
+    pub fn store_dir(&self) -> &Path {
+        &self.store_dir
+    }
+
+    /// A key that uniquely identifies the project of this config.
+    pub fn project_key(&self) -> &str {
+        &self.project_key
+    }
+
+    /// If enabled stale toolchains are updated automatically before
+    /// they are used instead of failing.
+    pub fn auto_update(&self) -> bool {
+        self.values.auto_update
+    }
+
+    /// The profile the config was loaded with.
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_ref().map(|x| x.as_str())
+    }
+
+    /// In offline mode calm does not access the network.
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    /// The names of the profiles the config defines.
+    pub fn profile_names(&self) -> Vec<String> {
+        if_chain! {
+            if let Value::Mapping(ref map) = self.merged_values;
+            if let Some(&Value::Mapping(ref profiles)) =
+                map.get(&Value::String("profiles".into()));
+            then {
+                profiles.iter().filter_map(|(key, _)| key.as_str())
+                    .map(|x| x.to_string())
+                    .collect()
+            } else {
+                vec![]
+            }
+        }
+    }
+
+    /// The profile a git hook runs with.
+    pub fn hook_profile(&self, hook: &str) -> Option<&str> {
+        self.values.hooks.get(hook)
+            .and_then(|x| x.profile.as_ref())
+            .map(|x| x.as_str())
+    }
+
+    /// Patterns of files that no tool should touch.
+    pub fn exclude(&self) -> &[Pattern] {
+        &self.values.exclude
+    }
+
+    /// If enabled files ignored by git are skipped.
+    pub fn respect_gitignore(&self) -> bool {
+        self.values.respect_gitignore
+    }
+
+    /// Returns the wheelhouse folders for offline python installs.
+    pub fn wheelhouses(&self) -> Vec<PathBuf> {
+        self.values.offline.wheelhouses.iter()
+            .map(|x| self.config_dir.join(x))
+            .collect()
+    }
+
+    /// Iterates over the tool ids in the order the tools run.
+    pub fn iter_tools(&self) -> SliceIter<String> {
+        self.tool_order.iter()
     }
 
     pub fn get_tool_spec(&self, id: &str) -> Option<&ToolSpec> {
         self.values.tools.get(id)
     }
+
+    /// Returns all tasks sorted by name.
+    pub fn iter_tasks(&self) -> Vec<(&str, &TaskSpec)> {
+        let mut rv: Vec<_> = self.values.tasks.iter()
+            .map(|(name, task)| (name.as_str(), task))
+            .collect();
+        rv.sort_by_key(|x| x.0);
+        rv
+    }
+
+    pub fn get_task(&self, name: &str) -> Option<&TaskSpec> {
+        self.values.tasks.get(name)
+    }
 }
 
 impl ToolSpec {
@@ -319,25 +1152,53 @@ impl ToolSpec {
     }
 }
 
-impl RemoteToolInclude {
-
-    pub fn path_prefix(&self) -> Option<&Path> {
+impl fmt::Display for RemoteToolInclude {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            RemoteToolInclude::Git { ref path, .. } => {
-                if let &Some(ref path) = path {
-                    let path = Path::new(path);
-                    if let Ok(rest) = path.strip_prefix("/") {
-                        return Some(rest);
-                    } else {
-                        return Some(path);
-                    }
+            RemoteToolInclude::Git { ref git, ref rev, ref path, .. } => {
+                write!(f, "git {}", git)?;
+                if let Some(ref rev) = *rev {
+                    write!(f, " @ {}", rev)?;
                 }
+                if let Some(ref path) = *path {
+                    write!(f, " ({})", path)?;
+                }
+                Ok(())
+            }
+            RemoteToolInclude::Archive { ref archive, ref path, .. } => {
+                write!(f, "archive {}", archive)?;
+                if let Some(ref path) = *path {
+                    write!(f, " ({})", path)?;
+                }
+                Ok(())
             }
-            RemoteToolInclude::Path { .. } => {
-                return None;
+            RemoteToolInclude::Path { ref path, ref prefix, .. } => {
+                write!(f, "path {}", path.display())?;
+                if let Some(ref prefix) = *prefix {
+                    write!(f, " ({})", prefix)?;
+                }
+                Ok(())
             }
         }
-        None
+    }
+}
+
+impl RemoteToolInclude {
+
+    pub fn path_prefix(&self) -> Option<&Path> {
+        let prefix = match *self {
+            RemoteToolInclude::Git { ref path, .. } |
+            RemoteToolInclude::Archive { ref path, .. } => path,
+            RemoteToolInclude::Path { ref prefix, .. } => prefix,
+        };
+        prefix.as_ref().map(|prefix| {
+            let path = Path::new(prefix);
+            if let Ok(rest) = path.strip_prefix("/") {
+                rest
+            } else {
+                path
+            }
+        })
     }
 
     pub fn local_path_reference<'a>(&'a self, config_dir: &Path,
@@ -346,12 +1207,53 @@ impl RemoteToolInclude {
             RemoteToolInclude::Git { .. } => {
                 cache_dir.join("tools").join(self.checksum())
             }
-            RemoteToolInclude::Path { ref path } => {
+            RemoteToolInclude::Archive { ref sha256, .. } => {
+                let digest = match *sha256 {
+                    Some(ref sha256) => sha256.to_lowercase(),
+                    None => get_cached_file_sha256(&self.archive_path(config_dir).unwrap(),
+                                                   cache_dir)
+                        .unwrap_or_else(|_| self.checksum()),
+                };
+                cache_dir.join("tools").join(digest)
+            }
+            RemoteToolInclude::Path { ref path, .. } => {
                 config_dir.join(path)
             }
         }
     }
 
+    /// Returns the local file of an archive include.  Archives can be
+    /// given as plain paths relative to the config or as `file://` URLs.
+    pub fn archive_path(&self, config_dir: &Path) -> Option<PathBuf> {
+        if let RemoteToolInclude::Archive { ref archive, .. } = *self {
+            let path = if archive.starts_with("file://") {
+                &archive[7..]
+            } else {
+                &archive[..]
+            };
+            Some(config_dir.join(path))
+        } else {
+            None
+        }
+    }
+
+    /// The parameter values the tool is included with.
+    pub fn with(&self) -> &HashMap<String, Value> {
+        match *self {
+            RemoteToolInclude::Git { ref with, .. } |
+            RemoteToolInclude::Archive { ref with, .. } |
+            RemoteToolInclude::Path { ref with, .. } => with,
+        }
+    }
+
+    /// Indicates that the include is materialized in the cache folder.
+    pub fn is_cached(&self) -> bool {
+        match *self {
+            RemoteToolInclude::Git { .. } | RemoteToolInclude::Archive { .. } => true,
+            RemoteToolInclude::Path { .. } => false,
+        }
+    }
+
     pub fn checksum(&self) -> String {
         let mut m = Sha1::new();
         match *self {
@@ -363,7 +1265,15 @@ impl RemoteToolInclude {
                     m.update(b"\x00");
                 }
             }
-            RemoteToolInclude::Path { ref path } => {
+            RemoteToolInclude::Archive { ref archive, ref sha256, .. } => {
+                m.update(archive.as_bytes());
+                m.update(b"\x00");
+                if let &Some(ref sha256) = sha256 {
+                    m.update(sha256.as_bytes());
+                    m.update(b"\x00");
+                }
+            }
+            RemoteToolInclude::Path { ref path, .. } => {
                 m.update(path.display().to_string().as_bytes());
                 m.update(b"\x00");
             }
@@ -1,12 +1,20 @@
 use std::fs;
 use std::env;
+use std::cmp;
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::collections::{HashMap, HashSet};
 
 use prelude::*;
-use config::{Config, RuntimeConfig, RemoteToolInclude};
+use config::{Config, RuntimeConfig, RemoteToolInclude, TaskSpec, ToolCommand,
+             fail_unknown_profile};
 use tools::Tool;
+use environ::Environment;
 use utils::cmd::CommandBuilder;
+use utils::git::checkout_rev;
+use utils::archive::extract_archive;
+use utils::ignore::{GitIgnore, is_path_excluded};
+use lock::{LockFile, LockedInclude};
 use report::Report;
 use formatting::FormatResult;
 use rt;
@@ -16,6 +24,7 @@ use console::{style, Term, user_attended};
 use parking_lot::Mutex;
 use walkdir::WalkDir;
 use indicatif::ProgressBar;
+use strsim::levenshtein;
 use which::which_in;
 
 #[derive(Debug)]
@@ -23,52 +32,140 @@ struct Log {
     lines: usize,
 }
 
+/// Restricts the tools a command runs to the ones given with `--tool`
+/// minus the ones given with `--skip`.
+#[derive(Debug, Default, Clone)]
+pub struct ToolFilter {
+    only: Vec<String>,
+    skip: Vec<String>,
+}
+
+impl ToolFilter {
+    pub fn new(only: Vec<String>, skip: Vec<String>) -> ToolFilter {
+        ToolFilter {
+            only: only,
+            skip: skip,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.only.is_empty() && self.skip.is_empty()
+    }
+
+    /// All tool ids mentioned by the filter.
+    pub fn tool_ids(&self) -> Vec<&str> {
+        self.only.iter().chain(self.skip.iter()).map(|x| x.as_str()).collect()
+    }
+
+    pub fn matches(&self, tool_id: &str) -> bool {
+        (self.only.is_empty() || self.only.iter().any(|x| x == tool_id)) &&
+            !self.skip.iter().any(|x| x == tool_id)
+    }
+}
+
 #[derive(Debug)]
 pub struct Context {
     base_dir: PathBuf,
     config: Config,
+    lock: LockFile,
+    offline: bool,
+    gitignore: Option<GitIgnore>,
+    tool_filter: ToolFilter,
     log: Mutex<Log>,
 }
 
-fn update_remote_tool(path: &Path, rti: &RemoteToolInclude) -> Result<()> {
+fn update_remote_tool(path: &Path, config_dir: &Path, rti: &RemoteToolInclude,
+                      locked: Option<&str>, offline: bool) -> Result<Option<String>> {
     match *rti {
         RemoteToolInclude::Git { ref git, ref rev, .. } => {
-            let mut cmd;
-            if fs::metadata(&path).is_err() {
-                fs::create_dir_all(&path)?;
-                cmd = CommandBuilder::new("git");
-                cmd
-                    .arg("clone")
-                    .arg(git)
-                    .arg(".")
-                    .current_dir(&path);
-
-                if let &Some(ref rev) = rev {
-                    cmd.arg("-b").arg(rev);
-                }
-            } else if rev.is_none() {
-                fs::create_dir_all(&path)?;
-                cmd = CommandBuilder::new("git");
-                cmd
-                    .arg("pull");
-            } else {
-                return Ok(());
-            }
+            Ok(Some(checkout_rev(path, git, rev.as_ref().map(|x| x.as_str()),
+                                 locked, offline)?))
+        }
+        RemoteToolInclude::Archive { ref sha256, .. } => {
+            extract_archive(&rti.archive_path(config_dir).unwrap(), path,
+                            sha256.as_ref().map(|x| x.as_str()))?;
+            Ok(None)
+        }
+        RemoteToolInclude::Path { .. } => Ok(None),
+    }
+}
 
-            cmd
-                .spawn()?
-                .wait()?;
+/// If the rev of a git include changed the old checkout is moved over
+/// so that switching to the new rev only needs to fetch the difference.
+fn move_previous_checkout(path: &Path, rti: &RemoteToolInclude,
+                          previous: &LockedInclude) -> Result<()> {
+    if_chain! {
+        if fs::metadata(path).is_err();
+        if let RemoteToolInclude::Git { ref git, .. } = *rti;
+        if git == &previous.git;
+        then {
+            let old_path = path.with_file_name(RemoteToolInclude::Git {
+                git: previous.git.clone(),
+                rev: previous.rev.clone(),
+                path: None,
+                with: HashMap::new(),
+            }.checksum());
+            if fs::metadata(&old_path).is_ok() {
+                fs::rename(&old_path, path)?;
+            }
         }
-        RemoteToolInclude::Path { .. } => {}
     }
     Ok(())
 }
 
+fn short_commit(commit: &str) -> &str {
+    &commit[..cmp::min(7, commit.len())]
+}
+
+/// Describes how the include of a tool changed for the update summary.
+fn describe_include_change(tool_id: &str, rti: &RemoteToolInclude,
+                           previous: Option<&LockedInclude>, commit: &str)
+    -> Option<String>
+{
+    let (rev, path) = match *rti {
+        RemoteToolInclude::Git { ref rev, ref path, .. } => (rev, path),
+        _ => return None,
+    };
+    let previous = match previous {
+        Some(previous) => previous,
+        None => {
+            return Some(format!("{}: {} ({})", tool_id,
+                                rev.as_ref().map(|x| x.as_str()).unwrap_or("HEAD"),
+                                short_commit(commit)));
+        }
+    };
+
+    if &previous.rev != rev {
+        Some(format!("{}: {} → {}", tool_id,
+                     previous.rev.as_ref().map(|x| x.as_str()).unwrap_or("HEAD"),
+                     rev.as_ref().map(|x| x.as_str()).unwrap_or("HEAD")))
+    } else if previous.commit != commit {
+        Some(format!("{}: {} → {}", tool_id,
+                     short_commit(&previous.commit), short_commit(commit)))
+    } else if &previous.path != path {
+        Some(format!("{}: path {} → {}", tool_id,
+                     previous.path.as_ref().map(|x| x.as_str()).unwrap_or("/"),
+                     path.as_ref().map(|x| x.as_str()).unwrap_or("/")))
+    } else {
+        None
+    }
+}
+
 impl Context {
     pub fn new(config: Config) -> Result<Context> {
+        let base_dir = config.config_dir().parent().unwrap().to_path_buf();
+        let gitignore = if config.respect_gitignore() {
+            GitIgnore::discover(&base_dir)
+        } else {
+            None
+        };
         Ok(Context {
-            base_dir: config.config_dir().parent().unwrap().to_path_buf(),
+            base_dir: base_dir,
+            lock: LockFile::load(&config.lock_filename())?,
+            offline: config.is_offline(),
             config: config,
+            gitignore: gitignore,
+            tool_filter: ToolFilter::default(),
             log: Mutex::new(Log {
                 lines: 0,
             }),
@@ -79,6 +176,14 @@ impl Context {
         &self.config.cache_dir()
     }
 
+    pub fn store_dir(&self) -> &Path {
+        &self.config.store_dir()
+    }
+
+    pub fn project_key(&self) -> &str {
+        self.config.project_key()
+    }
+
     pub fn base_dir(&self) -> &Path {
         &self.base_dir
     }
@@ -87,6 +192,80 @@ impl Context {
         &self.config
     }
 
+    pub fn lock(&self) -> &LockFile {
+        &self.lock
+    }
+
+    /// Returns a path relative to the base folder of the project.
+    pub fn relative_path<'a>(&self, path: &'a Path) -> &'a Path {
+        if let Ok(rel) = path.strip_prefix(&self.base_dir) {
+            return rel;
+        }
+        if_chain! {
+            if let Ok(base_dir) = self.base_dir.canonicalize();
+            if let Ok(rel) = path.strip_prefix(&base_dir);
+            then {
+                rel
+            } else {
+                path
+            }
+        }
+    }
+
+    /// Checks if a path is excluded by the config or ignored by git.
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        if is_path_excluded(self.config.exclude(), self.relative_path(path)) {
+            return true;
+        }
+        match self.gitignore {
+            Some(ref gitignore) => gitignore.is_ignored(&self.base_dir.join(path)),
+            None => false,
+        }
+    }
+
+    /// Switches to the profile configured for a git hook unless a
+    /// profile was selected explicitly.
+    pub fn use_hook_profile(&mut self, hook: &str) -> Result<()> {
+        if self.config.profile().is_some() {
+            return Ok(());
+        }
+        if let Some(profile) = self.config.hook_profile(hook).map(|x| x.to_string()) {
+            let config = Config::load(self.config.filename(), Some(&profile), self.offline)?;
+            if config.profile().is_none() {
+                fail_unknown_profile(&profile, &config.profile_names())?;
+            }
+            self.config = config;
+        }
+        Ok(())
+    }
+
+    pub fn set_tool_filter(&mut self, filter: ToolFilter) {
+        self.tool_filter = filter;
+    }
+
+    /// The ids of the tools that pass the tool filter.
+    pub fn active_tools(&self) -> Vec<String> {
+        self.config.iter_tools()
+            .filter(|x| self.tool_filter.matches(x))
+            .cloned()
+            .collect()
+    }
+
+    /// The ids of the tools the tool filter removed.
+    pub fn skipped_tools(&self) -> Vec<String> {
+        let mut rv: Vec<String> = self.config.iter_tools()
+            .filter(|x| !self.tool_filter.matches(x))
+            .cloned()
+            .collect();
+        rv.sort();
+        rv
+    }
+
+    /// In offline mode calm does not access the network.
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
     pub fn log_step(&self, text: &str) {
         let mut log = self.log.lock();
         write!(&mut ::std::io::stderr(), "{} {}\n",
@@ -122,6 +301,12 @@ impl Context {
     }
 
     pub fn clear_cache(&self) -> Result<()> {
+        // runtimes live in the shared store so we only drop our
+        // references.  The store deletes runtimes nobody uses.
+        for tool_id in self.config.iter_tools() {
+            self.create_tool(tool_id)?.release_runtimes()?;
+        }
+
         let wd = WalkDir::new(self.cache_dir());
         let count = wd.into_iter().count();
         let pb = ProgressBar::new(count as u64);
@@ -147,43 +332,235 @@ impl Context {
         Ok(())
     }
 
-    pub fn pull_dependencies(&mut self) -> Result<()> {
-        let mut changed = false;
+    /// Remembers which project the cache folder belongs to so that the
+    /// garbage collector can find it again.
+    pub fn register_project(&self) -> Result<()> {
+        fs::create_dir_all(self.cache_dir())?;
+        let mut f = fs::File::create(self.cache_dir().join("project"))?;
+        f.write_all(self.config.filename().display().to_string().as_bytes())?;
+        Ok(())
+    }
+
+    /// Returns all paths in the cache folder that the current config
+    /// still references.
+    pub fn referenced_cache_paths(&self) -> Result<HashSet<PathBuf>> {
+        let mut rv = HashSet::new();
         for tool_id in self.config.iter_tools() {
+            let tool = self.create_tool(tool_id)?;
+            tool.add_cache_paths(&mut rv);
+        }
+        Ok(rv)
+    }
+
+    /// Pulls the includes of all tools and returns a summary of the
+    /// includes that changed.
+    pub fn pull_dependencies(&mut self, relock: bool) -> Result<Vec<String>> {
+        if !self.offline {
+            self.config.update_bases()?;
+            self.config = self.config.reload()?;
+        }
+        let tool_ids = self.active_tools();
+        let changes = self.pull_tool_dependencies(&tool_ids, relock)?;
+        // profiles and filters only see some of the tools so nothing
+        // can be pruned
+        if self.config.profile().is_none() && self.tool_filter.is_empty() {
+            self.lock.retain_tools(&tool_ids);
+            {
+                let bases: Vec<_> = tool_ids.iter()
+                    .filter_map(|x| self.config.get_tool_spec(x))
+                    .flat_map(|x| x.bases.iter())
+                    .map(|x| &x.include)
+                    .collect();
+                self.lock.retain_bases(&bases);
+            }
+            self.lock.save(&self.config.lock_filename())?;
+            self.remove_stale_checkouts()?;
+        }
+        Ok(changes)
+    }
+
+    fn pull_tool_dependencies(&mut self, tool_ids: &[String], relock: bool)
+        -> Result<Vec<String>>
+    {
+        self.register_project()?;
+        let mut changed = false;
+        let mut changes = vec![];
+        for tool_id in tool_ids {
             let tool = self.config.get_tool_spec(tool_id).unwrap();
             if_chain! {
                 if let Some(ref rti) = tool.include;
                 if let Some(ref tool_dir_base) = tool.tool_dir_base;
                 then {
                     self.log_step(&format!("Pulling dependencies for '{}'", tool_id));
-                    update_remote_tool(&tool_dir_base, &rti)?;
+                    let previous = self.lock.get(tool_id).cloned();
+                    if let Some(ref previous) = previous {
+                        move_previous_checkout(tool_dir_base, rti, previous)?;
+                    }
+                    let locked = if relock {
+                        None
+                    } else {
+                        self.lock.get_commit(tool_id, rti).map(|x| x.to_string())
+                    };
+                    if let Some(commit) = update_remote_tool(
+                        &tool_dir_base, self.config.config_dir(), &rti,
+                        locked.as_ref().map(|x| x.as_str()), self.offline)?
+                    {
+                        if let Some(change) = describe_include_change(
+                            tool_id, rti, previous.as_ref(), &commit)
+                        {
+                            changes.push(change);
+                        }
+                        self.lock.record(tool_id, rti, &commit);
+                    }
                     changed = true;
                 }
             }
         }
 
         if changed {
-            self.config = Config::from_env()?;
+            self.lock.save(&self.config.lock_filename())?;
+            self.config = self.config.reload()?;
+        }
+
+        // extended tools only become known once the tool extending them
+        // is available so keep fetching until every base was pulled.
+        let mut pulled = HashSet::new();
+        loop {
+            let pending: Vec<_> = tool_ids.iter()
+                .filter_map(|x| self.config.get_tool_spec(x))
+                .flat_map(|x| x.bases.iter())
+                .filter(|x| x.include.is_cached() && !pulled.contains(&x.tool_dir_base))
+                .cloned()
+                .collect();
+            if pending.is_empty() {
+                break;
+            }
+            for base in pending {
+                self.log_step(&format!("Pulling extended tool {}", base.include));
+                pulled.insert(base.tool_dir_base.clone());
+                let locked = if relock {
+                    None
+                } else {
+                    self.lock.get_base_commit(&base.include).map(|x| x.to_string())
+                };
+                if let Some(commit) = update_remote_tool(
+                    &base.tool_dir_base, &base.config_dir, &base.include,
+                    locked.as_ref().map(|x| x.as_str()), self.offline)?
+                {
+                    self.lock.record_base(&base.include, &commit);
+                }
+            }
+            self.lock.save(&self.config.lock_filename())?;
+            self.config = self.config.reload()?;
         }
 
+        Ok(changes)
+    }
+
+    /// Removes checkouts of git includes that no tool uses anymore.
+    fn remove_stale_checkouts(&self) -> Result<()> {
+        let folder = self.cache_dir().join("tools");
+        if fs::metadata(&folder).is_err() {
+            return Ok(());
+        }
+
+        let used = self.referenced_cache_paths()?;
+        for entry in fs::read_dir(&folder)? {
+            let path = entry?.path();
+            if !used.contains(&path) {
+                self.log_step(&format!("Removing stale include {}", path.display()));
+                fs::remove_dir_all(&path)?;
+            }
+        }
         Ok(())
     }
 
-    pub fn update(&self) -> Result<()> {
+    pub fn update(&self, force: bool) -> Result<()> {
         self.log_step("Updating toolchains");
-        for tool_id in self.config.iter_tools() {
-            let tool = self.create_tool(tool_id)?;
-            tool.update()?;
+        for tool_id in self.active_tools() {
+            let tool = self.create_tool(&tool_id)?;
+            tool.update(force)?;
         }
         self.log_step("Updated");
         Ok(())
     }
 
+    /// In offline mode this checks up front that the given tools can be
+    /// updated and fails with a list of everything that would need the
+    /// network.
+    pub fn check_offline(&self, tool_ids: &[String], force: bool) -> Result<()> {
+        if !self.offline {
+            return Ok(());
+        }
+
+        let mut problems = vec![];
+        for tool_id in tool_ids {
+            self.create_tool(tool_id)?.check_offline(&mut problems, force)?;
+        }
+
+        if !problems.is_empty() {
+            fail!("cannot update offline:\n  - {}", problems.join("\n  - "));
+        }
+        Ok(())
+    }
+
+    /// Returns the ids of all tools that are missing their include or
+    /// have runtimes that are not up to date in the order they need to
+    /// be installed.
+    pub fn find_stale_tools(&self) -> Result<Vec<String>> {
+        let mut rv = vec![];
+        for tool_id in self.active_tools() {
+            let tool = self.create_tool(&tool_id)?;
+            if !tool.is_up_to_date()? {
+                rv.push(tool_id.to_string());
+            }
+        }
+        Ok(rv)
+    }
+
+    /// Makes sure all toolchains are ready before linting or formatting.
+    /// Stale toolchains are either updated or an error is returned
+    /// depending on the `auto-update` config setting.
+    pub fn ensure_toolchains(&mut self) -> Result<()> {
+        let stale = self.find_stale_tools()?;
+        if stale.is_empty() {
+            return self.reference_runtimes();
+        }
+
+        let mut names = stale.clone();
+        names.sort();
+        if !self.config.auto_update() {
+            if names.len() == 1 {
+                fail!("run `calm update` — tool {} is out of date", names[0]);
+            } else {
+                fail!("run `calm update` — tools {} are out of date", names.join(", "));
+            }
+        }
+
+        self.check_offline(&stale, false)?;
+        self.log_step(&format!("Updating stale toolchains: {}", names.join(", ")));
+        self.pull_tool_dependencies(&stale, false)?;
+        for tool_id in &stale {
+            self.create_tool(tool_id)?.update(false)?;
+        }
+        self.reference_runtimes()
+    }
+
+    /// Records that the project uses the runtimes of the active tools.
+    fn reference_runtimes(&self) -> Result<()> {
+        for tool_id in self.active_tools() {
+            self.create_tool(&tool_id)?.reference_runtimes()?;
+        }
+        Ok(())
+    }
+
     pub fn lint(&self, files: Option<&[&Path]>) -> Result<Report> {
         let mut report = Report::new(self);
+        report.add_skipped_tools(self.skipped_tools());
 
-        for tool_id in self.config.iter_tools() {
-            let tool = self.create_tool(tool_id)?;
+        for tool_id in self.active_tools() {
+            let tool = self.create_tool(&tool_id)?;
+            tool.verify_include()?;
             tool.lint(&mut report, files)?;
         }
 
@@ -198,8 +575,9 @@ impl Context {
             rv.register_file(file)?;
         }
 
-        for tool_id in self.config.iter_tools() {
-            let tool = self.create_tool(tool_id)?;
+        for tool_id in self.active_tools() {
+            let tool = self.create_tool(&tool_id)?;
+            tool.verify_include()?;
             if !tool.format(&mut rv, files)? {
                 fail!("formatter '{}' failed", tool_id);
             }
@@ -208,9 +586,98 @@ impl Context {
         Ok(rv)
     }
 
+    /// Returns the environment of the active tools.  Tools declared
+    /// first take precedence.  Variables that describe a tool (like
+    /// `CALM_TOOL_PATH`) are only set if a single tool is active.
+    pub fn environment(&self) -> Result<Environment> {
+        let tool_ids = self.active_tools();
+        let mut rv = Environment::new();
+        for tool_id in &tool_ids {
+            let tool = self.create_tool(tool_id)?;
+            rv.merge(if tool_ids.len() == 1 {
+                tool.environment(&tool.tool_dir())?
+            } else {
+                tool.runtime_environment()?
+            });
+        }
+        Ok(rv)
+    }
+
+    /// Runs a task from the config with extra arguments.
+    pub fn run_task(&self, name: &str, args: &[&str]) -> Result<()> {
+        let task = match self.config.get_task(name) {
+            Some(task) => task,
+            None => {
+                let names: Vec<_> = self.config.iter_tasks().into_iter()
+                    .map(|x| x.0)
+                    .collect();
+                match names.iter().min_by_key(|x| levenshtein(name, x)) {
+                    Some(suggestion) if levenshtein(name, suggestion) <= 3 => {
+                        fail!("unknown task '{}', did you mean '{}'?", name, suggestion);
+                    }
+                    _ => {
+                        fail!("unknown task '{}'", name);
+                    }
+                }
+            }
+        };
+
+        let tool = match task.tool {
+            Some(ref tool_id) => {
+                if self.config.get_tool_spec(tool_id).is_none() {
+                    fail!("task '{}' uses unknown tool '{}'", name, tool_id);
+                }
+                self.create_tool(tool_id)?
+            }
+            None => return self.run_plain_task(name, task, args),
+        };
+        tool.verify_include()?;
+        tool.run_task(task, args)
+    }
+
+    /// Runs a task without a tool in the project folder with the
+    /// environment calm was started with.
+    fn run_plain_task(&self, name: &str, task: &TaskSpec, args: &[&str]) -> Result<()> {
+        let last_cmd = task.run.iter().rposition(|x| x.cmd().is_some());
+        for (idx, step) in task.run.iter().enumerate() {
+            self.log_step(&step.description());
+            let mut cmd = match step.cmd() {
+                Some(&ToolCommand::Shell(ref cmdline)) => CommandBuilder::new_shell(cmdline),
+                Some(&ToolCommand::Exec(ref cmd_args)) => {
+                    if cmd_args.is_empty() {
+                        fail!("empty arguments in task '{}'", name);
+                    }
+                    let mut cmd = CommandBuilder::new(&cmd_args[0]);
+                    for arg in &cmd_args[1..] {
+                        cmd.arg(arg);
+                    }
+                    cmd
+                }
+                None => {
+                    fail!("task '{}' can only link files if it uses a tool", name);
+                }
+            };
+            cmd.current_dir(&self.base_dir);
+            if Some(idx) == last_cmd {
+                for arg in args {
+                    cmd.arg(arg);
+                }
+            }
+            self.clear_log();
+            let status = cmd.run_attached()?;
+            if !status.success() {
+                return Err(ErrorKind::QuietExit(status.code().unwrap_or(1)).into());
+            }
+        }
+        Ok(())
+    }
+
     pub fn is_lintable_file<P: AsRef<Path>>(&self, p: P) -> Result<bool> {
-        for tool_id in self.config.iter_tools() {
-            let t = self.create_tool(tool_id)?;
+        if self.is_excluded(p.as_ref()) {
+            return Ok(false);
+        }
+        for tool_id in self.active_tools() {
+            let t = self.create_tool(&tool_id)?;
             if t.does_lint_file(p.as_ref())? {
                 return Ok(true);
             }
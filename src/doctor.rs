@@ -0,0 +1,80 @@
+use std::fmt;
+use std::collections::BTreeSet;
+
+use prelude::*;
+use ctx::Context;
+use utils::hooks::HookManager;
+
+use console::style;
+use which::which;
+
+
+/// Something `calm doctor` found to be broken.
+#[derive(Debug)]
+pub struct Problem {
+    pub subject: String,
+    pub message: String,
+    pub fix: Option<String>,
+}
+
+impl Problem {
+    pub fn new<S: Into<String>, M: Into<String>>(subject: S, message: M,
+                                                 fix: Option<&str>) -> Problem {
+        Problem {
+            subject: subject.into(),
+            message: message.into(),
+            fix: fix.map(|x| x.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", style(&self.subject).cyan(), self.message)?;
+        if let Some(ref fix) = self.fix {
+            write!(f, "\n  {} {}", style("fix:").dim(), fix)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks that the commands the runtimes shell out to are available.
+fn check_commands(ctx: &Context, problems: &mut Vec<Problem>) -> Result<()> {
+    let mut commands = BTreeSet::new();
+    for tool_id in ctx.active_tools() {
+        commands.extend(ctx.create_tool(&tool_id)?.required_commands());
+    }
+    for cmd in commands {
+        if which(&cmd).is_err() {
+            problems.push(Problem::new(
+                cmd.as_str(), "command not found on the PATH",
+                Some(&format!("install {} or add it to the PATH", cmd))));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the git hook can find calm when it's installed.
+fn check_hook(problems: &mut Vec<Problem>) -> Result<()> {
+    let mgr = match HookManager::new() {
+        Ok(mgr) => mgr,
+        Err(_) => return Ok(()),
+    };
+    if mgr.status()?.pre_commit_installed && which("calm").is_err() {
+        problems.push(Problem::new(
+            "pre-commit hook", "the hook is installed but calm is not on the PATH",
+            Some("add calm to the PATH or run `calm hook --uninstall`")));
+    }
+    Ok(())
+}
+
+/// Runs all checks for a project and returns the problems found.
+pub fn diagnose(ctx: &Context) -> Result<Vec<Problem>> {
+    let mut problems = vec![];
+    check_commands(ctx, &mut problems)?;
+    for tool_id in ctx.active_tools() {
+        ctx.create_tool(&tool_id)?.diagnose(&mut problems)?;
+    }
+    check_hook(&mut problems)?;
+    Ok(problems)
+}
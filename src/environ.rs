@@ -0,0 +1,148 @@
+use std::env;
+use std::str;
+use std::path::{Path, PathBuf};
+use std::collections::BTreeMap;
+
+use prelude::*;
+use utils::cmd::CommandBuilder;
+
+use serde_json;
+
+
+/// The formats an environment can be exported in.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum EnvFormat {
+    Sh,
+    Fish,
+    Json,
+    Dotenv,
+}
+
+impl str::FromStr for EnvFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<EnvFormat> {
+        match s {
+            "sh" => Ok(EnvFormat::Sh),
+            "fish" => Ok(EnvFormat::Fish),
+            "json" => Ok(EnvFormat::Json),
+            "dotenv" => Ok(EnvFormat::Dotenv),
+            other => Err(Error::from(format!("Unknown format '{}'", other))),
+        }
+    }
+}
+
+fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+fn fish_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn dotenv_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"))
+}
+
+/// The search paths and environment variables tools run with.
+#[derive(Debug, Default, Clone)]
+pub struct Environment {
+    paths: Vec<PathBuf>,
+    vars: BTreeMap<String, String>,
+}
+
+impl Environment {
+    pub fn new() -> Environment {
+        Default::default()
+    }
+
+    pub fn add_path<P: AsRef<Path>>(&mut self, path: P) {
+        if !self.paths.iter().any(|x| x == path.as_ref()) {
+            self.paths.push(path.as_ref().to_path_buf());
+        }
+    }
+
+    pub fn set_var<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
+        self.vars.insert(key.into(), value.into());
+    }
+
+    /// Adds the paths and variables of another environment.  Values
+    /// that are already set take precedence.
+    pub fn merge(&mut self, other: Environment) {
+        for path in other.paths {
+            self.add_path(path);
+        }
+        for (key, value) in other.vars {
+            self.vars.entry(key).or_insert(value);
+        }
+    }
+
+    pub fn vars(&self) -> &BTreeMap<String, String> {
+        &self.vars
+    }
+
+    /// The value of `PATH` with the search paths in front of the
+    /// current one.
+    pub fn path_var(&self) -> String {
+        let mut path = String::new();
+        for item in &self.paths {
+            path.push_str(&format!("{}:", item.display()));
+        }
+        if let Ok(default_path) = env::var("PATH") {
+            path.push_str(&default_path);
+        }
+        path
+    }
+
+    /// Renders the environment for shells and other tools.  The shell
+    /// formats extend the `PATH` of the shell they are evaluated in.
+    pub fn export(&self, format: EnvFormat) -> Result<String> {
+        let mut rv = String::new();
+        match format {
+            EnvFormat::Sh => {
+                if !self.paths.is_empty() {
+                    let paths: Vec<_> = self.paths.iter()
+                        .map(|x| x.display().to_string())
+                        .collect();
+                    rv.push_str(&format!("export PATH={}:\"$PATH\"\n",
+                                         sh_quote(&paths.join(":"))));
+                }
+                for (key, value) in &self.vars {
+                    rv.push_str(&format!("export {}={}\n", key, sh_quote(value)));
+                }
+            }
+            EnvFormat::Fish => {
+                if !self.paths.is_empty() {
+                    let paths: Vec<_> = self.paths.iter()
+                        .map(|x| fish_quote(&x.display().to_string()))
+                        .collect();
+                    rv.push_str(&format!("set -gx PATH {} $PATH;\n", paths.join(" ")));
+                }
+                for (key, value) in &self.vars {
+                    rv.push_str(&format!("set -gx {} {};\n", key, fish_quote(value)));
+                }
+            }
+            EnvFormat::Json => {
+                let mut vars = self.vars.clone();
+                vars.insert("PATH".into(), self.path_var());
+                rv.push_str(&serde_json::to_string_pretty(&vars)?);
+                rv.push('\n');
+            }
+            EnvFormat::Dotenv => {
+                rv.push_str(&format!("PATH={}\n", dotenv_quote(&self.path_var())));
+                for (key, value) in &self.vars {
+                    rv.push_str(&format!("{}={}\n", key, dotenv_quote(value)));
+                }
+            }
+        }
+        Ok(rv)
+    }
+
+    /// Applies the environment to a command.
+    pub fn configure(&self, cmd: &mut CommandBuilder) {
+        cmd.env("PATH", self.path_var());
+        for (key, value) in &self.vars {
+            cmd.env(key, value);
+        }
+    }
+}
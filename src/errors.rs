@@ -5,6 +5,7 @@ use serde_yaml;
 use serde_json;
 use git2;
 use elementtree;
+use zip;
 
 
 error_chain! {
@@ -21,5 +22,6 @@ error_chain! {
         Json(serde_json::Error);
         Git(git2::Error);
         Xml(elementtree::Error);
+        Zip(zip::result::ZipError);
     }
 }
@@ -49,6 +49,11 @@ impl FormatResult {
         Ok(())
     }
 
+    /// Adds the files formatted by another context.
+    pub fn merge(&mut self, other: FormatResult) {
+        self.files.extend(other.files);
+    }
+
     pub fn get_scratch_file<P: AsRef<Path>>(&self, filename: P) -> Result<&Path> {
         self.files.get(&filename.as_ref().canonicalize()?).map(|x| x.path())
             .ok_or_else(|| Error::from("tried to get unregistered scratch file"))
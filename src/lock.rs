@@ -0,0 +1,138 @@
+use std::fs;
+use std::path::Path;
+use std::collections::BTreeMap;
+
+use prelude::*;
+use config::RemoteToolInclude;
+
+use serde_yaml;
+
+/// The commit a git include was resolved to.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LockedInclude {
+    pub git: String,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub rev: Option<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub path: Option<String>,
+    pub commit: String,
+}
+
+/// The lock file records the exact commits of all git includes so that
+/// everybody runs the same tools.  It lives next to the calm.yml.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct LockFile {
+    #[serde(default)]
+    includes: BTreeMap<String, LockedInclude>,
+    /// the commits of git includes that tools extend.
+    #[serde(default, skip_serializing_if="Vec::is_empty")]
+    bases: Vec<LockedInclude>,
+}
+
+impl LockFile {
+    pub fn load(path: &Path) -> Result<LockFile> {
+        if fs::metadata(path).is_err() {
+            return Ok(Default::default());
+        }
+        let mut f = fs::File::open(path)?;
+        Ok(serde_yaml::from_reader(&mut f)
+            .chain_err(|| "Failed to parse .calm/calm.lock")?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut f = fs::File::create(path)?;
+        serde_yaml::to_writer(&mut f, self)?;
+        Ok(())
+    }
+
+    pub fn get(&self, tool_id: &str) -> Option<&LockedInclude> {
+        self.includes.get(tool_id)
+    }
+
+    /// Returns the locked commit of a tool if the lock was made for the
+    /// same include.
+    pub fn get_commit(&self, tool_id: &str, rti: &RemoteToolInclude) -> Option<&str> {
+        if_chain! {
+            if let RemoteToolInclude::Git { ref git, ref rev, .. } = *rti;
+            if let Some(locked) = self.includes.get(tool_id);
+            if &locked.git == git && &locked.rev == rev;
+            then {
+                Some(locked.commit.as_str())
+            } else {
+                None
+            }
+        }
+    }
+
+    /// Checks if the lock of a tool was made for exactly this include.
+    pub fn is_current(&self, tool_id: &str, rti: &RemoteToolInclude) -> bool {
+        if_chain! {
+            if let RemoteToolInclude::Git { ref git, ref rev, ref path, .. } = *rti;
+            if let Some(locked) = self.includes.get(tool_id);
+            then {
+                &locked.git == git && &locked.rev == rev && &locked.path == path
+            } else {
+                false
+            }
+        }
+    }
+
+    pub fn record(&mut self, tool_id: &str, rti: &RemoteToolInclude, commit: &str) {
+        if let RemoteToolInclude::Git { ref git, ref rev, ref path, .. } = *rti {
+            self.includes.insert(tool_id.to_string(), LockedInclude {
+                git: git.to_string(),
+                rev: rev.clone(),
+                path: path.clone(),
+                commit: commit.to_string(),
+            });
+        }
+    }
+
+    /// Returns the locked commit of a git include a tool extends.
+    pub fn get_base_commit(&self, rti: &RemoteToolInclude) -> Option<&str> {
+        if let RemoteToolInclude::Git { ref git, ref rev, .. } = *rti {
+            self.bases.iter()
+                .find(|x| &x.git == git && &x.rev == rev)
+                .map(|x| x.commit.as_str())
+        } else {
+            None
+        }
+    }
+
+    pub fn record_base(&mut self, rti: &RemoteToolInclude, commit: &str) {
+        if let RemoteToolInclude::Git { ref git, ref rev, .. } = *rti {
+            self.bases.retain(|x| !(&x.git == git && &x.rev == rev));
+            self.bases.push(LockedInclude {
+                git: git.to_string(),
+                rev: rev.clone(),
+                path: None,
+                commit: commit.to_string(),
+            });
+            self.bases.sort_by(|a, b| (&a.git, &a.rev).cmp(&(&b.git, &b.rev)));
+        }
+    }
+
+    /// Drops the locks of all extended includes that are not in the
+    /// given list.
+    pub fn retain_bases(&mut self, includes: &[&RemoteToolInclude]) {
+        self.bases.retain(|locked| {
+            includes.iter().any(|rti| match **rti {
+                RemoteToolInclude::Git { ref git, ref rev, .. } => {
+                    &locked.git == git && &locked.rev == rev
+                }
+                _ => false,
+            })
+        });
+    }
+
+    /// Drops the locks of all tools that are not in the given list.
+    pub fn retain_tools(&mut self, tool_ids: &[String]) {
+        let stale: Vec<String> = self.includes.keys()
+            .filter(|x| !tool_ids.contains(*x))
+            .cloned()
+            .collect();
+        for tool_id in stale {
+            self.includes.remove(&tool_id);
+        }
+    }
+}
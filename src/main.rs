@@ -2,9 +2,10 @@ extern crate clap;
 extern crate serde;
 #[macro_use] extern crate serde_derive;
 extern crate serde_yaml;
-extern crate serde_json;
+#[macro_use] extern crate serde_json;
 #[macro_use] extern crate error_chain;
 extern crate sha1;
+extern crate sha2;
 extern crate dotenv;
 extern crate indicatif;
 extern crate console;
@@ -17,6 +18,12 @@ extern crate parking_lot;
 extern crate walkdir;
 extern crate which;
 extern crate tempfile;
+extern crate flate2;
+extern crate fs2;
+extern crate tar;
+extern crate zip;
+extern crate yaml_rust;
+extern crate strsim;
 extern crate notify;
 extern crate difflib;
 extern crate itertools;
@@ -26,15 +33,22 @@ extern crate itertools;
 mod macros;
 mod prelude;
 
+mod cache;
+mod catalogue;
 mod cli;
 mod config;
 mod ctx;
+mod doctor;
+mod environ;
 mod errors;
 mod report;
 mod formatting;
+mod lock;
 mod rt;
+mod schema;
 mod tools;
 mod utils;
+mod workspace;
 
 
 fn main() {
@@ -4,8 +4,8 @@ use std::str;
 use std::env;
 use std::fmt;
 use std::io::{BufRead, BufReader};
-use std::path::PathBuf;
-use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashMap};
 use std::borrow::Cow;
 
 use prelude::*;
@@ -15,6 +15,12 @@ use elementtree::Element;
 
 use regex::Regex;
 use console::{Style, style};
+use serde_json;
+
+/// Formats a path with forward slashes for use in a URI.
+fn path_to_uri(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
 
 lazy_static! {
     static ref IDENT_RE: Regex = Regex::new(
@@ -45,7 +51,8 @@ pub enum Format {
     Human,
     HumanExtended,
     Simple,
-    Checkstyle
+    Checkstyle,
+    Sarif,
 }
 
 impl str::FromStr for Format {
@@ -57,6 +64,7 @@ impl str::FromStr for Format {
             "human-extended" => Ok(Format::HumanExtended),
             "simple" => Ok(Format::Simple),
             "checkstyle" => Ok(Format::Checkstyle),
+            "sarif" => Ok(Format::Sarif),
             other => Err(Error::from(format!("Unknown format '{}'", other))),
         }
     }
@@ -71,6 +79,9 @@ pub struct LintResult {
     pub message: Option<String>,
     #[serde(default)]
     pub level: Level,
+    /// the documentation of the rule.
+    #[serde(skip)]
+    pub help_uri: Option<String>,
 }
 
 pub struct LintResultSimpleFormat<'a> {
@@ -164,6 +175,7 @@ impl LintResult {
 pub struct Report<'a> {
     lint_results: Vec<LintResult>,
     ctx: &'a Context,
+    skipped_tools: Vec<String>,
     linter_failed: bool,
     errors: u64,
     warnings: u64,
@@ -174,19 +186,25 @@ impl<'a> Report<'a> {
         Report {
             lint_results: vec![],
             ctx: ctx,
+            skipped_tools: vec![],
             linter_failed: false,
             errors: 0,
             warnings: 0,
         }
     }
 
+    /// Adds a result from a line the tool printed.  Results for files
+    /// excluded from the tool are dropped and `None` is returned.
     pub fn add_match_lint_result(&mut self, tool: &Tool, matches: &HashMap<Cow<str>, Cow<str>>)
-        -> Result<&LintResult>
+        -> Result<Option<&LintResult>>
     {
         let f = match matches.get("filename") {
             Some(f) => Some(self.ctx.base_dir().join(&f as &str).canonicalize()?),
             None => None,
         };
+        if f.as_ref().map(|x| tool.is_excluded(x)).unwrap_or(false) {
+            return Ok(None);
+        }
         self.push_result(LintResult {
             filename: f,
             line: matches.get("line").and_then(|x| x.parse().ok()).unwrap_or(0),
@@ -200,18 +218,26 @@ impl<'a> Report<'a> {
                     "info" => Level::Info,
                     _ => Level::Error,
                 }
-            }).unwrap_or(Level::Error)
-        })
+            }).unwrap_or(Level::Error),
+            help_uri: matches.get("code").and_then(|x| tool.rule_url(x)),
+        }).map(Some)
     }
 
+    /// Adds a result the tool reported as JSON.  Results for files
+    /// excluded from the tool are dropped and `None` is returned.
     pub fn add_lint_result(&mut self, tool: &Tool, mut res: LintResult)
-        -> Result<&LintResult>
+        -> Result<Option<&LintResult>>
     {
         if let Some(filename) = res.filename {
-            res.filename = Some(self.ctx.base_dir().join(&filename).canonicalize()?);
+            let filename = self.ctx.base_dir().join(&filename).canonicalize()?;
+            if tool.is_excluded(&filename) {
+                return Ok(None);
+            }
+            res.filename = Some(filename);
         }
+        res.help_uri = res.code.as_ref().and_then(|x| tool.rule_url(x));
         res.code = res.code.map(|code| format!("{}:{}", tool.id(), code));
-        self.push_result(res)
+        self.push_result(res).map(Some)
     }
 
     pub fn get_checkstyle_doc(&self) -> Element {
@@ -256,10 +282,83 @@ impl<'a> Report<'a> {
         rv
     }
 
+    /// Builds a minimal SARIF 2.1.0 log with one run for all tools.
+    pub fn get_sarif_doc(&self) -> serde_json::Value {
+        let base_dir = fs::canonicalize(self.ctx.base_dir())
+            .unwrap_or_else(|_| self.ctx.base_dir().to_path_buf());
+        let mut rules = BTreeMap::new();
+        let mut results = vec![];
+        for res in &self.lint_results {
+            let code = res.code.as_ref().map(|x| x.as_str()).unwrap_or("unknown");
+            rules.entry(code).or_insert_with(|| {
+                let mut rule = json!({"id": code});
+                if let Some(ref uri) = res.help_uri {
+                    rule["helpUri"] = json!(uri);
+                }
+                rule
+            });
+
+            let mut location = json!({});
+            if let Some(ref filename) = res.filename {
+                let filename = if filename.is_absolute() {
+                    fs::canonicalize(filename).unwrap_or_else(|_| filename.clone())
+                } else {
+                    filename.clone()
+                };
+                let uri = match filename.strip_prefix(&base_dir) {
+                    Ok(rel) => json!({"uri": path_to_uri(rel), "uriBaseId": "%SRCROOT%"}),
+                    Err(_) if filename.is_relative() => {
+                        json!({"uri": path_to_uri(&filename), "uriBaseId": "%SRCROOT%"})
+                    }
+                    Err(_) => json!({"uri": format!("file://{}", path_to_uri(&filename))}),
+                };
+                location["artifactLocation"] = uri;
+            }
+            if res.line > 0 {
+                location["region"] = json!({"startLine": res.line});
+                if res.column > 0 {
+                    location["region"]["startColumn"] = json!(res.column);
+                }
+            }
+
+            results.push(json!({
+                "ruleId": code,
+                "level": match res.level {
+                    Level::Error => "error",
+                    Level::Warning => "warning",
+                    Level::Info => "note",
+                },
+                "message": {
+                    "text": res.message.as_ref().map(|x| x.as_str()).unwrap_or("no info"),
+                },
+                "locations": [{"physicalLocation": location}],
+            }));
+        }
+
+        json!({
+            "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+            "version": "2.1.0",
+            "runs": [{
+                "tool": {
+                    "driver": {
+                        "name": "calm",
+                        "rules": rules.into_iter().map(|(_, x)| x).collect::<Vec<_>>(),
+                    },
+                },
+                "originalUriBaseIds": {
+                    "%SRCROOT%": {
+                        "uri": format!("file://{}/", path_to_uri(&base_dir)),
+                    },
+                },
+                "results": results,
+            }],
+        })
+    }
+
     fn push_result(&mut self, res: LintResult) -> Result<&LintResult> {
         let idx = self.lint_results.len();
         match res.level {
-            Level::Error => { self.linter_failed = true; self.errors += 1; }
+            Level::Error => { self.errors += 1; }
             Level::Warning => { self.warnings += 1; }
             _ => {}
         }
@@ -267,12 +366,53 @@ impl<'a> Report<'a> {
         Ok(&self.lint_results[idx])
     }
 
+    /// Adds the results of a report of another context.
+    pub fn merge(&mut self, other: Report<'a>) {
+        self.lint_results.extend(other.lint_results);
+        self.add_skipped_tools(other.skipped_tools);
+        self.linter_failed = self.linter_failed || other.linter_failed;
+        self.errors += other.errors;
+        self.warnings += other.warnings;
+    }
+
+    /// Drops the results of files below any of the given folders.
+    pub fn remove_files_below(&mut self, dirs: &[&Path]) {
+        let (removed, kept): (Vec<_>, Vec<_>) = self.lint_results.drain(..)
+            .partition(|res| {
+                res.filename.as_ref()
+                    .map(|x| dirs.iter().any(|dir| x.starts_with(dir)))
+                    .unwrap_or(false)
+            });
+        for res in removed {
+            match res.level {
+                Level::Error => { self.errors -= 1; }
+                Level::Warning => { self.warnings -= 1; }
+                _ => {}
+            }
+        }
+        self.lint_results = kept;
+    }
+
+    /// Records tools that were not run because of `--tool` or `--skip`.
+    pub fn add_skipped_tools(&mut self, tool_ids: Vec<String>) {
+        for tool_id in tool_ids {
+            if !self.skipped_tools.contains(&tool_id) {
+                self.skipped_tools.push(tool_id);
+            }
+        }
+        self.skipped_tools.sort();
+    }
+
+    pub fn skipped_tools(&self) -> &[String] {
+        &self.skipped_tools
+    }
+
     pub fn mark_failed(&mut self) {
         self.linter_failed = true;
     }
 
     pub fn did_fail(&self) -> bool {
-        self.linter_failed
+        self.linter_failed || self.errors > 0
     }
 
     pub fn has_errors(&self) -> bool {
@@ -296,6 +436,13 @@ impl<'a> Report<'a> {
             Format::Human | Format::HumanExtended => {
                 for res in &self.lint_results {
                     println!("{:#}", res);
+                    if_chain! {
+                        if format == Format::HumanExtended;
+                        if let Some(ref uri) = res.help_uri;
+                        then {
+                            println!("  {}", style(format!("see {}", uri)).dim());
+                        }
+                    }
                 }
 
                 if self.lint_results.is_empty() {
@@ -320,6 +467,11 @@ impl<'a> Report<'a> {
                         if self.warnings_count() != 1 { "s" } else { "" }
                     )));
                 }
+
+                if !self.skipped_tools.is_empty() {
+                    println!("{}", style(format!("Skipped tools: {}",
+                                                 self.skipped_tools.join(", "))).dim());
+                }
             }
             Format::Simple => {
                 for res in &self.lint_results {
@@ -330,6 +482,9 @@ impl<'a> Report<'a> {
                 let doc = self.get_checkstyle_doc();
                 doc.to_writer(&mut io::stdout())?;
             }
+            Format::Sarif => {
+                println!("{}", serde_json::to_string_pretty(&self.get_sarif_doc())?);
+            }
         }
         Ok(())
     }
@@ -1,18 +1,26 @@
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
 use std::fmt::Debug;
 use std::path::PathBuf;
 use std::ffi::OsStr;
+use std::collections::{BTreeMap, HashMap};
 
 use prelude::*;
 use config::RuntimeConfig;
 use ctx::Context;
+use doctor::Problem;
 use utils::cmd::CommandBuilder;
 
+use fs2::FileExt;
+use sha1::Sha1;
+
 pub trait Runtime<'a>: Debug + Sync {
     /// Return the context this runtime was created from.
-    fn ctx(&'a self) -> &'a Context;
+    fn ctx(&self) -> &Context;
 
     /// Return the context this runtime was created from.
-    fn config(&'a self) -> &'a RuntimeConfig;
+    fn config(&self) -> &RuntimeConfig;
 
     /// Returns the type name of the runtime.
     fn type_name(&self) -> &str;
@@ -38,10 +46,156 @@ pub trait Runtime<'a>: Debug + Sync {
         Ok(())
     }
 
-    /// Returns the path to where the runtime lives in the runtime
-    /// context.  This will also return a path in case the runtime
-    /// has not been created in the context yet.
-    fn get_path(&'a self) -> PathBuf {
-        self.ctx().cache_dir().join("rt").join(self.id().to_string())
+    /// Reports everything the update would need the network for.
+    fn check_offline(&self, _problems: &mut Vec<String>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Returns the commands the runtime needs on the `PATH` to be
+    /// installed.
+    fn required_commands(&self) -> Vec<String> {
+        vec![]
+    }
+
+    /// Reports problems with the installation of the runtime.
+    fn diagnose(&self, subject: &str, problems: &mut Vec<Problem>) -> Result<()> {
+        diagnose_installation(self, subject, problems)
+    }
+
+    /// Returns the path to where the runtime lives in the shared
+    /// store.  Runtimes are addressed by their config checksum so
+    /// identical runtimes are only installed once per machine.  This
+    /// will also return a path in case the runtime has not been
+    /// created yet.
+    fn get_path(&self) -> PathBuf {
+        self.ctx().store_dir().join("rt").join(self.id().to_string())
+    }
+
+    /// Returns a checksum over the installed state of the runtime
+    /// (lock files and similar) or `None` if it is not installed.
+    fn lock_state(&self) -> Result<Option<String>> {
+        if fs::metadata(self.get_path()).is_ok() {
+            Ok(Some("".to_string()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Returns the path of the stamp that marks the runtime as
+    /// installed.
+    fn get_stamp_path(&self) -> PathBuf {
+        self.get_path().join(".calm-stamp")
+    }
+
+    /// Takes an exclusive lock on the runtime in the store so that
+    /// concurrent updates do not install into the same folder.  The
+    /// lock is held until the returned file is dropped.
+    fn lock(&self) -> Result<fs::File> {
+        let path = self.ctx().store_dir().join("locks")
+            .join(format!("{}.lock", self.id()));
+        fs::create_dir_all(path.parent().unwrap())?;
+        let f = fs::File::create(&path)?;
+        f.lock_exclusive()?;
+        Ok(f)
+    }
+
+    /// Checks if the runtime is installed and its stamp matches the
+    /// current configuration and lock state.
+    fn is_up_to_date(&self) -> Result<bool> {
+        let lock_state = match self.lock_state()? {
+            Some(lock_state) => lock_state,
+            None => return Ok(false),
+        };
+        let mut stamp = String::new();
+        match fs::File::open(self.get_stamp_path()) {
+            Ok(mut f) => { f.read_to_string(&mut stamp)?; }
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+                return Ok(false);
+            }
+            Err(err) => { return Err(err.into()); }
+        }
+        Ok(stamp.trim() == lock_state)
+    }
+
+    /// Records the stamp after a successful update.
+    fn write_stamp(&self) -> Result<()> {
+        let lock_state = self.lock_state()?.ok_or_else(|| {
+            Error::from(format!("{} runtime was not installed", self.type_name()))
+        })?;
+        let path = self.get_stamp_path();
+        fs::create_dir_all(path.parent().unwrap())?;
+        let mut f = fs::File::create(&path)?;
+        f.write_all(lock_state.as_bytes())?;
+        Ok(())
+    }
+
+    /// Returns the folder that tracks which projects use the runtime.
+    fn get_refs_path(&self) -> PathBuf {
+        self.get_path().join(".calm-refs")
+    }
+
+    /// Records that the project of the context uses this runtime.
+    fn add_reference(&self) -> Result<()> {
+        let path = self.get_refs_path().join(self.ctx().project_key());
+        if fs::metadata(&path).is_ok() {
+            return Ok(());
+        }
+        fs::create_dir_all(path.parent().unwrap())?;
+        let mut f = fs::File::create(&path)?;
+        f.write_all(self.ctx().config().filename().display().to_string().as_bytes())?;
+        Ok(())
+    }
+
+    /// Removes the reference of the project of the context.  If no
+    /// other project uses the runtime it's deleted from the store.
+    /// Runtimes without any references were installed before they
+    /// were tracked and are kept.
+    fn remove_reference(&self) -> Result<()> {
+        let _lock = self.lock()?;
+        let path = self.get_refs_path();
+        fs::remove_file(path.join(self.ctx().project_key())).ok();
+        let unused = match fs::read_dir(&path) {
+            Ok(mut iter) => iter.next().is_none(),
+            Err(_) => false,
+        };
+        if unused && fs::metadata(self.get_path()).is_ok() {
+            fs::remove_dir_all(self.get_path())?;
+        }
+        Ok(())
+    }
+}
+
+/// Reports runtimes that are missing or out of date.
+pub fn diagnose_installation<'a, R: Runtime<'a> + ?Sized>(rt: &R, subject: &str,
+                                                          problems: &mut Vec<Problem>)
+    -> Result<()>
+{
+    if fs::metadata(rt.get_path()).is_err() {
+        problems.push(Problem::new(subject, "runtime is not installed",
+                                   Some("run `calm update`")));
+    } else if !rt.is_up_to_date()? {
+        problems.push(Problem::new(subject, "runtime is out of date",
+                                   Some("run `calm update`")));
+    }
+    Ok(())
+}
+
+/// Computes the checksum a runtime is addressed by in the store.
+pub fn get_config_checksum(type_name: &str, flavor: Option<&str>,
+                           packages: &HashMap<String, String>) -> String {
+    let mut sha = Sha1::new();
+    sha.update(type_name.as_bytes());
+    sha.update(b"\x00");
+    if let Some(flavor) = flavor {
+        sha.update(flavor.as_bytes());
+    }
+    sha.update(b"\x00");
+    let packages: BTreeMap<_, _> = packages.iter().collect();
+    for (pkg_name, version) in packages {
+        sha.update(pkg_name.as_bytes());
+        sha.update(b"=");
+        sha.update(version.as_bytes());
+        sha.update(b"\x00");
     }
+    sha.digest().to_string()
 }
@@ -1,17 +1,19 @@
 use std::fs;
-use std::io::Write;
-use std::path::PathBuf;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::ffi::OsStr;
+use std::process;
 
 use prelude::*;
 use config::RuntimeConfig;
 use ctx::Context;
-use rt::common::Runtime;
+use rt::common::{Runtime, get_config_checksum};
 use utils::cmd::CommandBuilder;
 
 use sha1::Sha1;
 use console::user_attended;
 use itertools::Itertools;
+use serde_json;
 
 #[derive(Debug)]
 pub struct JsRuntime<'a> {
@@ -24,17 +26,60 @@ impl<'a> JsRuntime<'a> {
     pub fn create(ctx: &'a Context, config: &'a RuntimeConfig)
         -> JsRuntime<'a>
     {
-        let mut sha1 = Sha1::new();
-        sha1.update(b"javascript\x00");
-
         JsRuntime {
             ctx: ctx,
-            config_hash: sha1.digest().to_string(),
+            config_hash: get_config_checksum(
+                "javascript", config.flavor(), config.packages()),
             config: config,
         }
     }
 }
 
+/// Returns the version of a package installed in a `node_modules`
+/// folder.
+fn get_installed_version(node_modules: &Path, pkg_name: &str) -> Option<String> {
+    let f = match fs::File::open(node_modules.join(pkg_name).join("package.json")) {
+        Ok(f) => f,
+        Err(_) => return None,
+    };
+    let manifest: serde_json::Value = match serde_json::from_reader(f) {
+        Ok(manifest) => manifest,
+        Err(_) => return None,
+    };
+    manifest.get("version").and_then(|x| x.as_str()).map(|x| x.to_string())
+}
+
+/// Checks if the yarn offline mirror has the tarball of a package.
+fn find_in_offline_mirror(mirror: &Path, pkg_name: &str, version: &str) -> bool {
+    let name = pkg_name.replace('/', "-");
+    mirror.join(format!("{}-{}.tgz", name, version)).is_file()
+}
+
+impl<'a> JsRuntime<'a> {
+    /// Asks yarn for the offline mirror it is configured with.
+    fn get_offline_mirror(&self) -> Option<PathBuf> {
+        let mut yarn = self.get_path().join("node_modules/.bin/yarn");
+        if !yarn.is_file() {
+            yarn = PathBuf::from("yarn");
+        }
+        let output = process::Command::new(&yarn)
+            .arg("config").arg("get").arg("yarn-offline-mirror")
+            .current_dir(self.ctx.base_dir())
+            .output();
+        match output {
+            Ok(ref output) if output.status.success() => {
+                let mirror = String::from_utf8_lossy(&output.stdout).trim().to_string();
+                if mirror.is_empty() || mirror == "undefined" {
+                    None
+                } else {
+                    Some(PathBuf::from(mirror))
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
 impl<'a> Runtime<'a> for JsRuntime<'a> {
     fn ctx(&self) -> &Context {
         self.ctx
@@ -52,6 +97,10 @@ impl<'a> Runtime<'a> for JsRuntime<'a> {
         "javascript"
     }
 
+    fn required_commands(&self) -> Vec<String> {
+        vec!["node".to_string(), "npm".to_string()]
+    }
+
     fn add_search_paths(&self, paths: &mut Vec<PathBuf>) -> Result<()> {
         paths.push(self.get_path().join("node_modules/.bin"));
         Ok(())
@@ -69,6 +118,48 @@ impl<'a> Runtime<'a> for JsRuntime<'a> {
         Ok(())
     }
 
+    fn lock_state(&self) -> Result<Option<String>> {
+        let path = self.get_path();
+        if !fs::metadata(path.join("node_modules/.bin/yarn")).is_ok() {
+            return Ok(None);
+        }
+
+        let mut sha1 = Sha1::new();
+        for filename in &["package.json", "yarn.lock"] {
+            let mut contents = vec![];
+            if let Ok(mut f) = fs::File::open(path.join(filename)) {
+                f.read_to_end(&mut contents)?;
+            }
+            sha1.update(&contents);
+            sha1.update(b"\x00");
+        }
+        Ok(Some(sha1.digest().to_string()))
+    }
+
+    fn check_offline(&self, problems: &mut Vec<String>) -> Result<()> {
+        let path = self.get_path();
+        if fs::metadata(path.join("node_modules/.bin/yarn")).is_err() {
+            problems.push("yarn is not installed in the javascript runtime yet".into());
+        }
+        let mirror = self.get_offline_mirror();
+        for (pkg_name, version) in self.config.packages() {
+            if get_installed_version(&path.join("node_modules"), pkg_name).as_ref() ==
+               Some(version) {
+                continue;
+            }
+            if_chain! {
+                if let Some(ref mirror) = mirror;
+                if find_in_offline_mirror(mirror, pkg_name, version);
+                then {
+                    continue;
+                }
+            }
+            problems.push(format!("javascript package {}@{} is neither installed nor in \
+                                   the yarn offline mirror", pkg_name, version));
+        }
+        Ok(())
+    }
+
     fn update(&self) -> Result<()> {
         let path = self.get_path();
 
@@ -102,6 +193,9 @@ impl<'a> Runtime<'a> for JsRuntime<'a> {
             if user_attended() {
                 cmd.arg("-d");
             }
+            if self.ctx.is_offline() {
+                cmd.arg("--offline");
+            }
             self.configure_run_step(&mut cmd)?;
             cmd.spawn()?.wait()?;
         }
@@ -114,6 +208,9 @@ impl<'a> Runtime<'a> for JsRuntime<'a> {
             cmd
                 .current_dir(&path)
                 .arg("add");
+            if self.ctx.is_offline() {
+                cmd.arg("--offline");
+            }
 
             for (ref pkg_name, ref version) in self.config.packages() {
                 cmd.arg(format!("{}@{}", pkg_name, version));
@@ -1,18 +1,46 @@
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::ffi::OsStr;
 
 use prelude::*;
 use config::RuntimeConfig;
 use ctx::Context;
-use rt::common::Runtime;
+use rt::common::{Runtime, get_config_checksum, diagnose_installation};
+use doctor::Problem;
 use utils::cmd::CommandBuilder;
 
 use sha1::Sha1;
 use itertools::Itertools;
+use walkdir::WalkDir;
 
 const DEFAULT_FLAVOR: &'static str = "python3";
 
+/// Normalizes a distribution name or filename the way pip compares them.
+fn normalize_dist_name(name: &str) -> String {
+    name.to_lowercase().replace("_", "-")
+}
+
+/// Checks if a wheelhouse has a wheel or sdist for a package.
+fn find_in_wheelhouse(wheelhouse: &Path, pkg_name: &str, version: &str) -> bool {
+    let prefix = normalize_dist_name(&format!("{}-{}", pkg_name, version));
+    if let Ok(iter) = fs::read_dir(wheelhouse) {
+        for entry in iter {
+            if_chain! {
+                if let Ok(entry) = entry;
+                if let Some(filename) = entry.file_name().to_str();
+                then {
+                    let filename = normalize_dist_name(filename);
+                    if filename.starts_with(&format!("{}-", prefix)) ||
+                       filename.starts_with(&format!("{}.", prefix)) {
+                        return true;
+                    }
+                }
+            }
+        }
+    }
+    false
+}
+
 #[derive(Debug)]
 pub struct PythonRuntime<'a> {
     ctx: &'a Context,
@@ -24,17 +52,11 @@ impl<'a> PythonRuntime<'a> {
     pub fn create(ctx: &'a Context, config: &'a RuntimeConfig)
         -> PythonRuntime<'a>
     {
-        let mut sha1 = Sha1::new();
-        sha1.update(b"python\x00");
-        if let Some(flavor) = config.flavor() {
-            sha1.update(flavor.as_bytes());
-        } else {
-            sha1.update(DEFAULT_FLAVOR.as_bytes());
-        }
-
         PythonRuntime {
             ctx: ctx,
-            config_hash: sha1.digest().to_string(),
+            config_hash: get_config_checksum(
+                "python", Some(config.flavor().unwrap_or(DEFAULT_FLAVOR)),
+                config.packages()),
             config: config,
         }
     }
@@ -73,8 +95,69 @@ impl<'a> Runtime<'a> for PythonRuntime<'a> {
         Ok(())
     }
 
+    fn lock_state(&self) -> Result<Option<String>> {
+        let path = self.get_path();
+        if !fs::metadata(path.join("bin").join("python")).is_ok() {
+            return Ok(None);
+        }
+
+        // pip has no lock file so we go by the distributions that are
+        // installed into the site-packages of the virtualenv.
+        let mut dists = vec![];
+        for entry in WalkDir::new(path.join("lib")).max_depth(3) {
+            if_chain! {
+                if let Ok(entry) = entry;
+                if let Some(name) = entry.file_name().to_str();
+                if name.ends_with(".dist-info") || name.ends_with(".egg-info");
+                then {
+                    dists.push(name.to_string());
+                }
+            }
+        }
+        dists.sort();
+
+        let mut sha1 = Sha1::new();
+        for dist in dists {
+            sha1.update(dist.as_bytes());
+            sha1.update(b"\x00");
+        }
+        Ok(Some(sha1.digest().to_string()))
+    }
+
+    fn required_commands(&self) -> Vec<String> {
+        let mut rv = vec!["virtualenv".to_string()];
+        if let Some(flavor) = self.config.flavor() {
+            rv.push(flavor.to_string());
+        }
+        rv
+    }
+
+    fn diagnose(&self, subject: &str, problems: &mut Vec<Problem>) -> Result<()> {
+        // the venv links to the interpreter it was created from
+        let python = self.get_path().join("bin").join("python");
+        if fs::symlink_metadata(&python).is_ok() && fs::metadata(&python).is_err() {
+            problems.push(Problem::new(
+                subject, "the virtualenv points to a python that no longer exists",
+                Some("run `calm update --force`")));
+            return Ok(());
+        }
+        diagnose_installation(self, subject, problems)
+    }
+
+    fn check_offline(&self, problems: &mut Vec<String>) -> Result<()> {
+        let wheelhouses = self.ctx.config().wheelhouses();
+        for (pkg_name, version) in self.config.packages() {
+            if !wheelhouses.iter().any(|x| find_in_wheelhouse(x, pkg_name, version)) {
+                problems.push(format!("python package {}=={} is not in any wheelhouse",
+                                      pkg_name, version));
+            }
+        }
+        Ok(())
+    }
+
     fn update(&self) -> Result<()> {
         let path = self.get_path();
+        let offline = self.ctx.is_offline();
 
         fs::create_dir_all(&path)?;
 
@@ -87,18 +170,23 @@ impl<'a> Runtime<'a> for PythonRuntime<'a> {
             if let Some(flavor) = self.config.flavor() {
                 cmd.arg("-p").arg(flavor);
             }
+            if offline {
+                cmd.arg("--no-download");
+            }
             cmd.spawn()?.wait()?;
         }
 
         // Ensure we have a recent pip
-        self.ctx.log_step("Updating pip");
-        CommandBuilder::new("bin/pip")
-            .current_dir(&path)
-            .arg("install")
-            .arg("--upgrade")
-            .arg("pip")
-            .spawn()?
-            .wait()?;
+        if !offline {
+            self.ctx.log_step("Updating pip");
+            CommandBuilder::new("bin/pip")
+                .current_dir(&path)
+                .arg("install")
+                .arg("--upgrade")
+                .arg("pip")
+                .spawn()?
+                .wait()?;
+        }
 
         // install dependencies
         if !self.config.packages().is_empty() {
@@ -109,6 +197,13 @@ impl<'a> Runtime<'a> for PythonRuntime<'a> {
                 .current_dir(&path)
                 .arg("install");
 
+            if offline {
+                cmd.arg("--no-index");
+                for wheelhouse in self.ctx.config().wheelhouses() {
+                    cmd.arg("--find-links").arg(wheelhouse);
+                }
+            }
+
             for (ref pkg_name, ref version) in self.config.packages() {
                 cmd.arg(format!("{}=={}", pkg_name, version));
             }
@@ -0,0 +1,538 @@
+use std::collections::HashMap;
+use std::fs;
+use std::fmt;
+use std::io::Read;
+use std::path::Path;
+use std::result::Result as StdResult;
+
+use prelude::*;
+
+use serde_json;
+use strsim::levenshtein;
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::{Marker, TScalarStyle};
+
+/// The expected shape of a value in a config file.
+#[derive(Debug, Clone)]
+pub enum Schema {
+    Any,
+    String,
+    Bool,
+    Int,
+    /// any string, number or boolean.
+    Scalar,
+    Enum(Vec<&'static str>),
+    List(Box<Schema>),
+    /// a mapping with arbitrary keys.
+    Map(Box<Schema>),
+    Object {
+        fields: Vec<(&'static str, Schema)>,
+        required: Vec<&'static str>,
+    },
+    OneOf(Vec<Schema>),
+}
+
+/// A problem found in a config file.
+#[derive(Debug, Clone)]
+pub struct ValidationError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Mark {
+    line: usize,
+    column: usize,
+}
+
+impl From<Marker> for Mark {
+    fn from(marker: Marker) -> Mark {
+        Mark {
+            line: marker.line(),
+            column: marker.col() + 1,
+        }
+    }
+}
+
+/// A YAML document that remembers where every value was defined.
+#[derive(Debug, Clone)]
+enum Node {
+    Scalar(String, bool, Mark),
+    Sequence(Vec<Node>, Mark),
+    Mapping(Vec<(Node, Node)>, Mark),
+}
+
+impl Node {
+    fn mark(&self) -> Mark {
+        match *self {
+            Node::Scalar(_, _, mark) |
+            Node::Sequence(_, mark) |
+            Node::Mapping(_, mark) => mark,
+        }
+    }
+
+    fn is_null(&self) -> bool {
+        match *self {
+            Node::Scalar(ref value, plain, _) => {
+                plain && (value == "" || value == "~" || value == "null")
+            }
+            _ => false,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match *self {
+            Node::Scalar(..) => "a value",
+            Node::Sequence(..) => "a list",
+            Node::Mapping(..) => "a mapping",
+        }
+    }
+}
+
+#[derive(Default)]
+struct NodeBuilder {
+    stack: Vec<(Node, Option<Node>, usize)>,
+    anchors: HashMap<usize, Node>,
+    root: Option<Node>,
+}
+
+impl NodeBuilder {
+    fn push(&mut self, node: Node, anchor: usize) {
+        if anchor > 0 {
+            self.anchors.insert(anchor, node.clone());
+        }
+        let parent = match self.stack.last_mut() {
+            Some(parent) => parent,
+            None => {
+                self.root = Some(node);
+                return;
+            }
+        };
+        match *parent {
+            (Node::Sequence(ref mut items, _), _, _) => items.push(node),
+            (Node::Mapping(ref mut items, _), ref mut key, _) => {
+                match key.take() {
+                    Some(key) => items.push((key, node)),
+                    None => *key = Some(node),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl MarkedEventReceiver for NodeBuilder {
+    fn on_event(&mut self, event: Event, marker: Marker) {
+        match event {
+            Event::Scalar(value, style, anchor, _) => {
+                let node = Node::Scalar(value, style == TScalarStyle::Plain, marker.into());
+                self.push(node, anchor);
+            }
+            // validate aliases as the value of the anchor they refer to,
+            // which is also what serde sees
+            Event::Alias(anchor) => {
+                let node = self.anchors.get(&anchor).cloned()
+                    .unwrap_or_else(|| Node::Scalar("".into(), true, marker.into()));
+                self.push(node, 0);
+            }
+            Event::SequenceStart(anchor) => {
+                self.stack.push((Node::Sequence(vec![], marker.into()), None, anchor));
+            }
+            Event::MappingStart(anchor) => {
+                self.stack.push((Node::Mapping(vec![], marker.into()), None, anchor));
+            }
+            Event::SequenceEnd | Event::MappingEnd => {
+                if let Some((node, _, anchor)) = self.stack.pop() {
+                    self.push(node, anchor);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn parse_nodes(source: &str) -> StdResult<Option<Node>, ValidationError> {
+    let mut builder = NodeBuilder::default();
+    if let Err(err) = Parser::new(source.chars()).load(&mut builder, false) {
+        let mark = Mark::from(*err.marker());
+        return Err(ValidationError {
+            line: mark.line,
+            column: mark.column,
+            message: err.to_string(),
+        });
+    }
+    Ok(builder.root)
+}
+
+fn suggest<'a>(name: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    candidates.iter()
+        .map(|x| (levenshtein(name, x), *x))
+        .filter(|&(dist, _)| dist <= 3)
+        .min_by_key(|&(dist, _)| dist)
+        .map(|(_, x)| x)
+}
+
+impl Schema {
+    fn describe(&self) -> String {
+        match *self {
+            Schema::Any => "anything".into(),
+            Schema::String => "a string".into(),
+            Schema::Bool => "a boolean".into(),
+            Schema::Int => "an integer".into(),
+            Schema::Scalar => "a string, number or boolean".into(),
+            Schema::Enum(ref values) => format!("one of {}", values.join(", ")),
+            Schema::List(_) => "a list".into(),
+            Schema::Map(_) => "a mapping".into(),
+            Schema::Object { ref required, .. } => {
+                if required.is_empty() {
+                    "a mapping".into()
+                } else {
+                    format!("a mapping with `{}`", required.join("`, `"))
+                }
+            }
+            Schema::OneOf(ref variants) => {
+                let descs: Vec<_> = variants.iter().map(|x| x.describe()).collect();
+                descs.join(" or ")
+            }
+        }
+    }
+
+    fn error(&self, node: &Node, errors: &mut Vec<ValidationError>) {
+        let mark = node.mark();
+        errors.push(ValidationError {
+            line: mark.line,
+            column: mark.column,
+            message: format!("expected {}, found {}", self.describe(), node.kind()),
+        });
+    }
+
+    fn validate_node(&self, node: &Node, errors: &mut Vec<ValidationError>) {
+        // null removes inherited values and stands for missing values
+        if node.is_null() {
+            return;
+        }
+
+        match (self, node) {
+            (&Schema::Any, _) => {}
+            (&Schema::String, &Node::Scalar(..)) |
+            (&Schema::Scalar, &Node::Scalar(..)) => {}
+            (&Schema::Bool, &Node::Scalar(ref value, plain, _))
+                if plain && (value == "true" || value == "false") => {}
+            (&Schema::Int, &Node::Scalar(ref value, plain, _))
+                if plain && value.parse::<i64>().is_ok() => {}
+            (&Schema::Enum(ref values), &Node::Scalar(ref value, _, mark)) => {
+                if !values.iter().any(|x| *x == value.as_str()) {
+                    let mut message = format!("unknown value `{}`, expected one of {}",
+                                              value, values.join(", "));
+                    if let Some(suggestion) = suggest(value, values) {
+                        message = format!("{} (did you mean `{}`?)", message, suggestion);
+                    }
+                    errors.push(ValidationError {
+                        line: mark.line,
+                        column: mark.column,
+                        message: message,
+                    });
+                }
+            }
+            (&Schema::List(ref item), &Node::Sequence(ref items, _)) => {
+                for node in items {
+                    item.validate_node(node, errors);
+                }
+            }
+            (&Schema::Map(ref value), &Node::Mapping(ref items, _)) => {
+                for &(_, ref node) in items {
+                    value.validate_node(node, errors);
+                }
+            }
+            (&Schema::Object { ref fields, ref required }, &Node::Mapping(ref items, mark)) => {
+                let names: Vec<_> = fields.iter().map(|x| x.0).collect();
+                for &(ref key, ref value) in items {
+                    let name = match *key {
+                        Node::Scalar(ref name, _, _) => name,
+                        _ => {
+                            self.error(key, errors);
+                            continue;
+                        }
+                    };
+                    match fields.iter().find(|x| x.0 == name.as_str()) {
+                        Some(&(_, ref schema)) => schema.validate_node(value, errors),
+                        None => {
+                            let key_mark = key.mark();
+                            let mut message = format!("unknown field `{}`", name);
+                            if let Some(suggestion) = suggest(name, &names) {
+                                message = format!("{}, did you mean `{}`?", message, suggestion);
+                            } else {
+                                message = format!("{}, expected one of `{}`",
+                                                  message, names.join("`, `"));
+                            }
+                            errors.push(ValidationError {
+                                line: key_mark.line,
+                                column: key_mark.column,
+                                message: message,
+                            });
+                        }
+                    }
+                }
+                for name in required {
+                    let present = items.iter().any(|&(ref key, _)| match *key {
+                        Node::Scalar(ref key, _, _) => key == name,
+                        _ => false,
+                    });
+                    if !present {
+                        errors.push(ValidationError {
+                            line: mark.line,
+                            column: mark.column,
+                            message: format!("missing field `{}`", name),
+                        });
+                    }
+                }
+            }
+            (&Schema::OneOf(ref variants), node) => {
+                let mut candidate = None;
+                for variant in variants {
+                    let mut variant_errors = vec![];
+                    variant.validate_node(node, &mut variant_errors);
+                    if variant_errors.is_empty() {
+                        return;
+                    }
+                    // report the errors of the first variant whose
+                    // required fields are all present.
+                    if candidate.is_none() && variant.has_required_fields(node) {
+                        candidate = Some(variant_errors);
+                    }
+                }
+                match candidate {
+                    Some(variant_errors) => errors.extend(variant_errors),
+                    None => self.error(node, errors),
+                }
+            }
+            (schema, node) => schema.error(node, errors),
+        }
+    }
+
+    fn has_required_fields(&self, node: &Node) -> bool {
+        match (self, node) {
+            (&Schema::Object { ref required, .. }, &Node::Mapping(ref items, _)) => {
+                required.iter().all(|name| items.iter().any(|&(ref key, _)| match *key {
+                    Node::Scalar(ref key, _, _) => key == name,
+                    _ => false,
+                }))
+            }
+            (&Schema::List(_), &Node::Sequence(..)) => true,
+            (&Schema::Map(_), &Node::Mapping(..)) => true,
+            _ => false,
+        }
+    }
+
+    /// Validates a YAML document against the schema.
+    pub fn validate(&self, source: &str) -> Vec<ValidationError> {
+        let mut errors = vec![];
+        match parse_nodes(source) {
+            Ok(Some(node)) => self.validate_node(&node, &mut errors),
+            Ok(None) => {}
+            Err(err) => errors.push(err),
+        }
+        errors
+    }
+
+    /// Converts the schema into a JSON Schema for editors.
+    pub fn to_json_schema(&self) -> serde_json::Value {
+        match *self {
+            Schema::Any => json!({}),
+            Schema::String => json!({"type": "string"}),
+            Schema::Bool => json!({"type": "boolean"}),
+            Schema::Int => json!({"type": "integer"}),
+            Schema::Scalar => json!({"type": ["string", "number", "boolean"]}),
+            Schema::Enum(ref values) => json!({"enum": values}),
+            Schema::List(ref item) => json!({
+                "type": "array",
+                "items": item.to_json_schema(),
+            }),
+            Schema::Map(ref value) => json!({
+                "type": "object",
+                "additionalProperties": value.to_json_schema(),
+            }),
+            Schema::Object { ref fields, ref required } => {
+                let mut properties = serde_json::Map::new();
+                for &(name, ref schema) in fields {
+                    properties.insert(name.to_string(), schema.to_json_schema());
+                }
+                json!({
+                    "type": "object",
+                    "properties": properties,
+                    "required": required,
+                    "additionalProperties": false,
+                })
+            }
+            Schema::OneOf(ref variants) => json!({
+                "oneOf": variants.iter().map(|x| x.to_json_schema()).collect::<Vec<_>>(),
+            }),
+        }
+    }
+}
+
+fn object(fields: Vec<(&'static str, Schema)>, required: Vec<&'static str>) -> Schema {
+    Schema::Object {
+        fields: fields,
+        required: required,
+    }
+}
+
+fn list(item: Schema) -> Schema {
+    Schema::List(Box::new(item))
+}
+
+fn map(value: Schema) -> Schema {
+    Schema::Map(Box::new(value))
+}
+
+fn include_schema() -> Schema {
+    Schema::OneOf(vec![
+        object(vec![
+            ("git", Schema::String),
+            ("rev", Schema::String),
+            ("path", Schema::String),
+            ("with", map(Schema::Scalar)),
+        ], vec!["git"]),
+        object(vec![
+            ("archive", Schema::String),
+            ("sha256", Schema::String),
+            ("path", Schema::String),
+            ("with", map(Schema::Scalar)),
+        ], vec!["archive"]),
+        object(vec![
+            ("path", Schema::String),
+            ("prefix", Schema::String),
+            ("with", map(Schema::Scalar)),
+        ], vec!["path"]),
+    ])
+}
+
+fn stream_actions_schema() -> Schema {
+    object(vec![
+        ("parse-lines", object(vec![
+            ("pattern", Schema::String),
+            ("report-match", Schema::Enum(vec!["lint-result"])),
+        ], vec!["pattern", "report-match"])),
+        ("parse-lint-json", Schema::Bool),
+    ], vec![])
+}
+
+fn step_schema() -> Schema {
+    Schema::OneOf(vec![
+        object(vec![
+            ("description", Schema::String),
+            ("cmd", Schema::OneOf(vec![Schema::String, list(Schema::String)])),
+            ("stdout", stream_actions_schema()),
+            ("stderr", stream_actions_schema()),
+        ], vec!["cmd"]),
+        object(vec![
+            ("description", Schema::String),
+            ("link", Schema::String),
+        ], vec!["link"]),
+    ])
+}
+
+fn tool_schema() -> Schema {
+    let run_spec = || object(vec![
+        ("patterns", list(Schema::String)),
+        ("run", list(step_schema())),
+    ], vec!["patterns", "run"]);
+    object(vec![
+        ("include", include_schema()),
+        ("extends", include_schema()),
+        ("description", Schema::String),
+        ("depends-on", list(Schema::String)),
+        ("params", map(object(vec![
+            ("type", Schema::Enum(vec!["string", "int", "bool"])),
+            ("default", Schema::Scalar),
+            ("description", Schema::String),
+        ], vec![]))),
+        ("exclude", list(Schema::String)),
+        ("runtimes", map(object(vec![
+            ("flavor", Schema::String),
+            ("packages", map(Schema::Scalar)),
+        ], vec![]))),
+        ("install", list(step_schema())),
+        ("lint", run_spec()),
+        ("format", run_spec()),
+        ("docs", object(vec![
+            ("url", Schema::String),
+            ("dir", Schema::String),
+        ], vec![])),
+    ], vec![])
+}
+
+/// The schema of `.calm/calm.yml`.
+pub fn config_schema() -> Schema {
+    object(vec![
+        ("extends", Schema::OneOf(vec![
+            Schema::String,
+            object(vec![
+                ("git", Schema::String),
+                ("rev", Schema::String),
+                ("path", Schema::String),
+            ], vec!["git"]),
+        ])),
+        ("tools", map(tool_schema())),
+        ("rules", list(object(vec![
+            ("patterns", list(Schema::String)),
+            ("run", Schema::String),
+        ], vec!["patterns", "run"]))),
+        ("auto-update", Schema::Bool),
+        ("offline", object(vec![
+            ("wheelhouses", list(Schema::String)),
+        ], vec![])),
+        ("exclude", list(Schema::String)),
+        ("respect-gitignore", Schema::Bool),
+        ("tasks", map(object(vec![
+            ("description", Schema::String),
+            ("tool", Schema::String),
+            ("run", list(step_schema())),
+        ], vec!["run"]))),
+        ("profiles", map(object(vec![
+            ("tools", list(Schema::String)),
+            ("override", map(tool_schema())),
+        ], vec![]))),
+        ("hooks", object(vec![
+            ("pre-commit", object(vec![
+                ("profile", Schema::String),
+            ], vec![])),
+        ], vec![])),
+    ], vec![])
+}
+
+/// The schema of the `calmtool.yml` of included tools.
+pub fn tool_config_schema() -> Schema {
+    object(vec![
+        ("tool", tool_schema()),
+    ], vec!["tool"])
+}
+
+/// Validates a file and returns all problems found.
+pub fn validate_file(schema: &Schema, filename: &Path) -> Result<Vec<ValidationError>> {
+    let mut source = String::new();
+    fs::File::open(filename)
+        .chain_err(|| format!("Could not open {}", filename.display()))?
+        .read_to_string(&mut source)?;
+    Ok(schema.validate(&source))
+}
+
+/// Validates a file and fails with all problems found.
+pub fn ensure_valid_file(schema: &Schema, filename: &Path) -> Result<()> {
+    let errors = validate_file(schema, filename)?;
+    if !errors.is_empty() {
+        let lines: Vec<_> = errors.iter()
+            .map(|x| format!("  {}:{}", filename.display(), x))
+            .collect();
+        fail!("invalid config {}:\n{}", filename.display(), lines.join("\n"));
+    }
+    Ok(())
+}
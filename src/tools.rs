@@ -1,15 +1,20 @@
 use std::fs;
+use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::borrow::Cow;
-use std::os::unix::fs::symlink;
+use std::os::unix::fs::{symlink, PermissionsExt};
 use std::sync::Arc;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use prelude::*;
 use ctx::Context;
 use rt::common::Runtime;
-use utils::cmd::{CommandBuilder, CommandHandlers};
-use config::{ToolSpec, ToolStep, ToolCommand, ReportPatternMatch};
+use utils::cmd::{CommandBuilder, CommandHandlers, expand_known_vars, expand_known_shell_vars};
+use utils::git::get_head_commit;
+use utils::ignore::is_path_excluded;
+use config::{ToolSpec, ToolStep, ToolCommand, ReportPatternMatch, RemoteToolInclude, TaskSpec};
+use environ::Environment;
+use doctor::Problem;
 use report::Report;
 use formatting::FormatResult;
 
@@ -25,10 +30,37 @@ pub struct Tool<'a> {
     runtimes: Vec<Box<Runtime<'a> + 'a>>,
 }
 
+/// A runtime of a tool as reported by `calm tools`.
+#[derive(Serialize, Debug)]
+pub struct RuntimeInfo {
+    #[serde(rename="type")]
+    pub ty: String,
+    pub flavor: Option<String>,
+    pub packages: BTreeMap<String, String>,
+    pub installed: bool,
+}
+
+/// Everything `calm tools` reports about a tool.
+#[derive(Serialize, Debug)]
+pub struct ToolInfo {
+    pub id: String,
+    pub description: Option<String>,
+    pub source: Option<String>,
+    pub commit: Option<String>,
+    pub depends_on: Vec<String>,
+    pub runtimes: Vec<RuntimeInfo>,
+    pub lint_patterns: Option<Vec<String>>,
+    pub format_patterns: Option<Vec<String>>,
+    pub installed: bool,
+    pub up_to_date: bool,
+}
+
 #[derive(Default, Debug)]
 pub struct RunStepOptions<'a, 'b: 'a, 'c> {
     report: Option<&'a mut Report<'b>>,
     file_args: Vec<&'c Path>,
+    extra_args: Vec<&'c str>,
+    attached: bool,
 }
 
 impl<'a> Tool<'a> {
@@ -65,30 +97,74 @@ impl<'a> Tool<'a> {
         Ok(())
     }
 
-    pub fn run_step(&self, step: &ToolStep,
-                    opts: Option<&mut RunStepOptions>) -> Result<bool> {
-        let mut path = vec![];
+    /// Adds all paths in the cache folder this tool uses.
+    pub fn add_cache_paths(&self, paths: &mut HashSet<PathBuf>) {
+        if_chain! {
+            if let Some(ref rti) = self.spec.include;
+            if rti.is_cached();
+            if let Some(ref tool_dir_base) = self.spec.tool_dir_base;
+            then {
+                paths.insert(tool_dir_base.to_path_buf());
+            }
+        }
+        for base in &self.spec.bases {
+            if base.include.is_cached() {
+                paths.insert(base.tool_dir_base.to_path_buf());
+            }
+        }
         for rt in &self.runtimes {
-            rt.add_search_paths(&mut path)?;
+            paths.insert(rt.get_path());
         }
-        self.ctx.log_step(&step.description());
+    }
 
-        // build the updated environment variables
-        let mut env = HashMap::new();
+    /// Builds the search paths and environment variables the steps of
+    /// the tool run with.
+    pub fn environment(&self, tool_dir: &Path) -> Result<Environment> {
+        let mut env = self.runtime_environment()?;
+        env.set_var("CALM_TOOL_PATH", tool_dir.display().to_string());
+        for (name, value) in &self.spec.param_values {
+            env.set_var(format!("CALM_PARAM_{}", name.to_uppercase().replace('-', "_")),
+                        value.to_string());
+        }
+        Ok(env)
+    }
+
+    /// Returns the search paths and variables of the runtimes without
+    /// the variables that describe the tool itself.
+    pub fn runtime_environment(&self) -> Result<Environment> {
+        let mut env = Environment::new();
+        let mut paths = vec![];
         for rt in &self.runtimes {
+            rt.add_search_paths(&mut paths)?;
             rt.update_env(&mut |key, value| {
-                env.insert(key.to_string_lossy().to_string(),
-                           value.to_string_lossy().to_string());
+                env.set_var(key.to_string_lossy(), value.to_string_lossy());
             })?;
         }
+        for path in paths {
+            env.add_path(path);
+        }
+        Ok(env)
+    }
+
+    pub fn run_step(&self, step: &ToolStep,
+                    opts: Option<&mut RunStepOptions>) -> Result<bool> {
+        self.ctx.log_step(&step.description());
 
-        env.insert("CALM_TOOL_PATH".to_string(),
-                   self.tool_dir().display().to_string());
+        // steps inherited from an extended tool run relative to it
+        let tool_dir = match step.tool_dir() {
+            Some(tool_dir) => Cow::Borrowed(tool_dir),
+            None => self.tool_dir(),
+        };
+        let env = self.environment(&tool_dir)?;
 
         // link resources
         if let Some(res) = step.link() {
-            let from_path = self.tool_dir().join(&res.src(Some(&env)));
-            let target_path = self.ctx.base_dir().join(&res.dst(Some(&env)));
+            let mut vars: HashMap<String, String> = env.vars().clone().into_iter().collect();
+            for (name, value) in &self.spec.param_values {
+                vars.insert(name.to_string(), value.to_string());
+            }
+            let from_path = tool_dir.join(&res.src(Some(&vars)));
+            let target_path = self.ctx.base_dir().join(&res.dst(Some(&vars)));
             fs::remove_file(&target_path).ok();
             symlink(from_path, target_path)?;
             Ok(true)
@@ -99,25 +175,24 @@ impl<'a> Tool<'a> {
             let mut cmd;
             match tool_cmd {
                 &ToolCommand::Shell(ref cmdline) => {
-                    cmd = CommandBuilder::new_shell(cmdline);
+                    cmd = CommandBuilder::new_shell(
+                        &expand_known_shell_vars(cmdline, &self.spec.param_values));
                 }
                 &ToolCommand::Exec(ref args) => {
                     if args.is_empty() {
                         return Err(Error::from("empty arguments for tool step"));
                     }
-                    cmd = CommandBuilder::new(&args[0]);
+                    cmd = CommandBuilder::new(
+                        &expand_known_vars(&args[0], &self.spec.param_values));
                     for arg in &args[1..] {
-                        cmd.arg(arg);
+                        cmd.arg(expand_known_vars(arg, &self.spec.param_values).as_ref());
                     }
                 }
             }
 
             // configure process
-            cmd.search_path(&path);
             cmd.current_dir(self.ctx.base_dir());
-            for (ref key, ref value) in &env {
-                cmd.env(key, value);
-            }
+            env.configure(&mut cmd);
             for rt in &self.runtimes {
                 rt.configure_run_step(&mut cmd)?;
             }
@@ -128,6 +203,21 @@ impl<'a> Tool<'a> {
                     cmd.arg(file_arg);
                 }
             }
+            if let Some(extra_args) = opts.as_ref().map(|x| &x.extra_args[..]) {
+                for extra_arg in extra_args {
+                    cmd.arg(extra_arg);
+                }
+            }
+
+            // tasks talk to the user directly
+            if opts.as_ref().map(|x| x.attached).unwrap_or(false) {
+                self.ctx.clear_log();
+                let status = cmd.run_attached()?;
+                if !status.success() {
+                    return Err(ErrorKind::QuietExit(status.code().unwrap_or(1)).into());
+                }
+                return Ok(true);
+            }
 
             let process = cmd.spawn()?;
             let mut handlers: CommandHandlers = Default::default();
@@ -146,14 +236,15 @@ impl<'a> Tool<'a> {
                                         if parse_lines.report_match == ReportPatternMatch::LintResult {
                                             let mut rep = report.lock();
                                             let res = rep.add_match_lint_result(self, &m)?;
-                                            return Ok(match res.filename {
-                                                Some(ref filename) => {
+                                            return Ok(match res.map(|x| &x.filename) {
+                                                Some(&Some(ref filename)) => {
                                                     Cow::Owned(format!(
                                                         "Found issue in {}", filename.display()))
                                                 },
-                                                None => {
+                                                Some(&None) => {
                                                     Cow::Borrowed("Found new general issue")
                                                 }
+                                                None => Cow::Borrowed("Linting ..."),
                                             });
                                         }
                                     }
@@ -184,9 +275,14 @@ impl<'a> Tool<'a> {
         }
     }
 
-    pub fn update(&self) -> Result<()> {
+    pub fn update(&self, force: bool) -> Result<()> {
         for rt in &self.runtimes {
-            rt.update()?;
+            let _lock = rt.lock()?;
+            if force || !rt.is_up_to_date()? {
+                rt.update()?;
+                rt.write_stamp()?;
+            }
+            rt.add_reference()?;
         }
 
         for step in &self.spec.install_steps {
@@ -196,7 +292,288 @@ impl<'a> Tool<'a> {
         Ok(())
     }
 
+    /// Collects everything that keeps this tool from being updated
+    /// without network access.
+    pub fn check_offline(&self, problems: &mut Vec<String>, force: bool) -> Result<()> {
+        if_chain! {
+            if let Some(RemoteToolInclude::Git { ref git, .. }) = self.spec.include;
+            if let Some(ref tool_dir_base) = self.spec.tool_dir_base;
+            if fs::metadata(tool_dir_base).is_err();
+            then {
+                problems.push(format!("{}: git include {} is not checked out",
+                                      self.id, git));
+            }
+        }
+        for rt in &self.runtimes {
+            if force || !rt.is_up_to_date()? {
+                let mut rt_problems = vec![];
+                rt.check_offline(&mut rt_problems)?;
+                for problem in rt_problems {
+                    problems.push(format!("{}: {}", self.id, problem));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Records that the project uses the runtimes of the tool.
+    pub fn reference_runtimes(&self) -> Result<()> {
+        for rt in &self.runtimes {
+            rt.add_reference()?;
+        }
+        Ok(())
+    }
+
+    /// Drops the references of the project to the runtimes of the tool.
+    pub fn release_runtimes(&self) -> Result<()> {
+        for rt in &self.runtimes {
+            rt.remove_reference()?;
+        }
+        Ok(())
+    }
+
+    /// Makes sure the checkout of a git include is at the commit that
+    /// is recorded in the lock file and that archive includes and
+    /// extended tools are available.
+    pub fn verify_include(&self) -> Result<()> {
+        if_chain! {
+            if let Some(ref rti @ RemoteToolInclude::Git { .. }) = self.spec.include;
+            if let Some(ref tool_dir_base) = self.spec.tool_dir_base;
+            then {
+                let locked = match self.ctx.lock().get_commit(&self.id, rti) {
+                    Some(locked) => locked,
+                    None => {
+                        fail!("tool '{}' is not locked; run `calm update`", self.id);
+                    }
+                };
+                if !self.ctx.lock().is_current(&self.id, rti) {
+                    fail!("the include of tool '{}' changed; run `calm update`", self.id);
+                }
+                match get_head_commit(tool_dir_base)? {
+                    Some(ref head) if head == locked => {}
+                    Some(head) => {
+                        fail!("tool '{}' is checked out at {} but locked to {}; \
+                               run `calm update`", self.id, head, locked);
+                    }
+                    None => {
+                        fail!("tool '{}' is not checked out; run `calm update`", self.id);
+                    }
+                }
+            }
+        }
+        if_chain! {
+            if let Some(RemoteToolInclude::Archive { .. }) = self.spec.include;
+            if let Some(ref tool_dir_base) = self.spec.tool_dir_base;
+            if fs::metadata(tool_dir_base).is_err();
+            then {
+                fail!("the archive of tool '{}' is not extracted; run `calm update`",
+                      self.id);
+            }
+        }
+        for base in &self.spec.bases {
+            if base.include.is_cached() && fs::metadata(&base.tool_dir_base).is_err() {
+                fail!("a tool extended by '{}' is not available; run `calm update`",
+                      self.id);
+            }
+            if let RemoteToolInclude::Git { .. } = base.include {
+                let locked = match self.ctx.lock().get_base_commit(&base.include) {
+                    Some(locked) => locked,
+                    None => {
+                        fail!("tool {} extended by '{}' is not locked; run `calm update`",
+                              base.include, self.id);
+                    }
+                };
+                if get_head_commit(&base.tool_dir_base)?.as_ref().map(|x| x.as_str()) !=
+                   Some(locked) {
+                    fail!("tool {} extended by '{}' is not at its locked commit {}; \
+                           run `calm update`", base.include, self.id, locked);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks if the include and all runtimes of the tool are
+    /// installed and match the current config.
+    pub fn is_up_to_date(&self) -> Result<bool> {
+        if self.verify_include().is_err() {
+            return Ok(false);
+        }
+        for rt in &self.runtimes {
+            if !rt.is_up_to_date()? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Collects what the config and the installation say about the
+    /// tool.
+    pub fn describe(&self) -> Result<ToolInfo> {
+        let mut runtimes = vec![];
+        for rt in &self.runtimes {
+            runtimes.push(RuntimeInfo {
+                ty: rt.type_name().to_string(),
+                flavor: rt.config().flavor().map(|x| x.to_string()),
+                packages: rt.config().packages().iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+                installed: fs::metadata(rt.get_path()).is_ok(),
+            });
+        }
+
+        let include_available = match self.spec.tool_dir_base {
+            Some(ref tool_dir_base) => fs::metadata(tool_dir_base).is_ok(),
+            None => true,
+        };
+
+        Ok(ToolInfo {
+            id: self.id.clone(),
+            description: self.spec.description.clone(),
+            source: self.spec.include.as_ref().map(|x| x.to_string()),
+            commit: self.spec.include.as_ref()
+                .and_then(|rti| self.ctx.lock().get_commit(&self.id, rti))
+                .map(|x| x.to_string()),
+            depends_on: self.spec.depends_on.clone(),
+            installed: include_available && runtimes.iter().all(|x| x.installed),
+            runtimes: runtimes,
+            lint_patterns: self.spec.lint.as_ref().map(|x| {
+                x.patterns.iter().map(|x| x.to_string()).collect()
+            }),
+            format_patterns: self.spec.format.as_ref().map(|x| {
+                x.patterns.iter().map(|x| x.to_string()).collect()
+            }),
+            up_to_date: self.is_up_to_date()?,
+        })
+    }
+
+    /// Returns the documentation url of a rule if the tool has a url
+    /// template.
+    pub fn rule_url(&self, code: &str) -> Option<String> {
+        let template = match self.spec.docs.as_ref().and_then(|x| x.url.as_ref()) {
+            Some(template) => template,
+            None => return None,
+        };
+        let mut vars = self.spec.param_values.clone();
+        vars.insert("code".to_string(), code.to_string());
+        Some(expand_known_vars(template, &vars).to_string())
+    }
+
+    /// Reads the description of a rule from the docs folder of the
+    /// tool.
+    pub fn rule_description(&self, code: &str) -> Result<Option<String>> {
+        let dir = match self.spec.docs.as_ref().and_then(|x| x.dir.as_ref()) {
+            Some(dir) => self.tool_dir().join(dir),
+            None => return Ok(None),
+        };
+        // codes come from the command line so keep them inside the folder
+        if code.is_empty() || code.contains('/') || code.starts_with('.') {
+            return Ok(None);
+        }
+        for ext in &["md", "txt"] {
+            let path = dir.join(format!("{}.{}", code, ext));
+            if let Ok(mut f) = fs::File::open(&path) {
+                let mut rv = String::new();
+                f.read_to_string(&mut rv)?;
+                return Ok(Some(rv));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Checks if the tool documents its rules.
+    pub fn has_rule_docs(&self) -> bool {
+        self.spec.docs.as_ref().map(|x| x.url.is_some() || x.dir.is_some()).unwrap_or(false)
+    }
+
+    /// The commands the runtimes of the tool need to be installed.
+    pub fn required_commands(&self) -> Vec<String> {
+        self.runtimes.iter().flat_map(|x| x.required_commands()).collect()
+    }
+
+    /// Reports everything that keeps the tool from running.
+    pub fn diagnose(&self, problems: &mut Vec<Problem>) -> Result<()> {
+        let subject = format!("tool {}", self.id);
+        if let Err(err) = self.verify_include() {
+            problems.push(Problem::new(subject.as_str(), err.to_string(), None));
+            return Ok(());
+        }
+        for rt in &self.runtimes {
+            rt.diagnose(&format!("{} ({} runtime)", subject, rt.type_name()), problems)?;
+        }
+
+        let steps = self.spec.install_steps.iter()
+            .chain(self.spec.lint.iter().flat_map(|x| x.run.iter()))
+            .chain(self.spec.format.iter().flat_map(|x| x.run.iter()));
+        for step in steps {
+            let tool_dir = match step.tool_dir() {
+                Some(tool_dir) => Cow::Borrowed(tool_dir),
+                None => self.tool_dir(),
+            };
+            let mut vars: HashMap<String, String> = self.environment(&tool_dir)?
+                .vars().clone().into_iter().collect();
+            for (name, value) in &self.spec.param_values {
+                vars.insert(name.to_string(), value.to_string());
+            }
+
+            // links point from the project into the tool folder
+            if let Some(res) = step.link() {
+                let target_path = self.ctx.base_dir().join(&res.dst(Some(&vars)));
+                if fs::symlink_metadata(&target_path).is_err() {
+                    problems.push(Problem::new(
+                        subject.as_str(),
+                        format!("{} is not linked", target_path.display()),
+                        Some("run `calm update`")));
+                } else if fs::metadata(&target_path).is_err() {
+                    problems.push(Problem::new(
+                        subject.as_str(),
+                        format!("{} is a dangling link", target_path.display()),
+                        Some("run `calm update --force`")));
+                }
+            }
+
+            // scripts shipped with the tool have to be executable
+            let program = match step.cmd() {
+                Some(&ToolCommand::Shell(ref cmdline)) => cmdline.split_whitespace().next(),
+                Some(&ToolCommand::Exec(ref args)) => args.get(0).map(|x| x.as_str()),
+                None => None,
+            };
+            if let Some(program) = program {
+                let script = expand_known_vars(program, &vars).to_string();
+                if !script.contains('/') {
+                    continue;
+                }
+                let path = self.ctx.base_dir().join(&script);
+                match fs::metadata(&path) {
+                    Err(_) => {
+                        problems.push(Problem::new(
+                            subject.as_str(),
+                            format!("script {} does not exist", path.display()),
+                            None));
+                    }
+                    Ok(ref md) if md.permissions().mode() & 0o111 == 0 => {
+                        problems.push(Problem::new(
+                            subject.as_str(),
+                            format!("script {} is not executable", path.display()),
+                            Some(&format!("run `chmod +x {}`", path.display()))));
+                    }
+                    Ok(_) => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks if a file is excluded for this tool.
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        self.ctx.is_excluded(path) ||
+            is_path_excluded(&self.spec.exclude, self.ctx.relative_path(path))
+    }
+
     pub fn does_lint_file(&self, path: &Path) -> Result<bool> {
+        if self.is_excluded(path) {
+            return Ok(false);
+        }
         if let Some(ref lint_spec) = self.spec.lint {
             for pat in &lint_spec.patterns {
                 if pat.match_path(path) {
@@ -216,6 +593,9 @@ impl<'a> Tool<'a> {
                 let mut opts = RunStepOptions {
                     report: Some(report),
                     file_args: files.map(|x| x.iter().filter_map(|&x| {
+                        if self.is_excluded(x) {
+                            return None;
+                        }
                         for pat in &lint_spec.patterns {
                             if pat.match_path(x) {
                                 return Some(x.strip_prefix(base).unwrap_or(x));
@@ -223,6 +603,7 @@ impl<'a> Tool<'a> {
                         }
                         None
                     }).collect()).unwrap_or(vec![]),
+                    ..Default::default()
                 };
 
                 // if no files are passed to the runner but an explicit file
@@ -250,11 +631,29 @@ impl<'a> Tool<'a> {
         }
     }
 
+    /// Runs the steps of a task in the environment of this tool.  The
+    /// extra arguments are passed to the last command of the task.
+    pub fn run_task(&self, task: &TaskSpec, args: &[&str]) -> Result<()> {
+        let last_cmd = task.run.iter().rposition(|x| x.cmd().is_some());
+        for (idx, step) in task.run.iter().enumerate() {
+            let mut opts = RunStepOptions {
+                extra_args: if Some(idx) == last_cmd { args.to_vec() } else { vec![] },
+                attached: true,
+                ..Default::default()
+            };
+            self.run_step(step, Some(&mut opts))?;
+        }
+        Ok(())
+    }
+
     pub fn format(&self, fr: &mut FormatResult, files: &[&Path]) -> Result<bool> {
         if let Some(ref format_spec) = self.spec.format {
             let mut failed = false;
             let mut file_args = vec![];
             for file in files.iter() {
+                if self.is_excluded(file) {
+                    continue;
+                }
                 for pat in &format_spec.patterns {
                     if pat.match_path(file) {
                         file_args.push(fr.get_scratch_file(file)?);
@@ -268,8 +667,8 @@ impl<'a> Tool<'a> {
             }
 
             let mut opts = RunStepOptions {
-                report: None,
                 file_args: file_args,
+                ..Default::default()
             };
             for step in &format_spec.run {
                 if !self.run_step(step, Some(&mut opts))? {
@@ -0,0 +1,133 @@
+use std::io;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use std::os::unix::fs::PermissionsExt;
+
+use flate2::read::GzDecoder;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use tar;
+use zip::ZipArchive;
+
+use prelude::*;
+
+
+/// Returns the sha256 of a file as hex string.
+pub fn get_file_sha256(path: &Path) -> Result<String> {
+    let mut f = fs::File::open(path)?;
+    let mut sha = Sha256::default();
+    let mut buf = [0u8; 8192];
+    loop {
+        let read = f.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        sha.input(&buf[..read]);
+    }
+    Ok(sha.result().iter().map(|x| format!("{:02x}", x)).collect())
+}
+
+/// Returns the sha256 of a file and remembers it in a cache folder for
+/// as long as the size and modification time of the file stay the same.
+pub fn get_cached_file_sha256(path: &Path, cache_dir: &Path) -> Result<String> {
+    let md = fs::metadata(path)?;
+    let mtime = md.modified()?.duration_since(UNIX_EPOCH)
+        .map(|x| format!("{}.{:09}", x.as_secs(), x.subsec_nanos()))
+        .unwrap_or_default();
+    let key = format!("{} {}", mtime, md.len());
+
+    let mut sha = Sha1::new();
+    sha.update(path.to_string_lossy().as_bytes());
+    let cache_file = cache_dir.join("digests").join(sha.digest().to_string());
+
+    let mut contents = String::new();
+    if let Ok(mut f) = fs::File::open(&cache_file) {
+        f.read_to_string(&mut contents)?;
+        let mut parts = contents.rsplitn(2, ' ');
+        if let (Some(digest), Some(cached_key)) = (parts.next(), parts.next()) {
+            if cached_key == key {
+                return Ok(digest.to_string());
+            }
+        }
+    }
+
+    let digest = get_file_sha256(path)?;
+    fs::create_dir_all(cache_file.parent().unwrap())?;
+    let mut f = fs::File::create(&cache_file)?;
+    f.write_all(format!("{} {}", key, digest).as_bytes())?;
+    Ok(digest)
+}
+
+fn unpack_zip(archive: &Path, dst: &Path) -> Result<()> {
+    let mut zip = ZipArchive::new(fs::File::open(archive)?)?;
+    for idx in 0..zip.len() {
+        let mut file = zip.by_index(idx)?;
+        let path = dst.join(file.sanitized_name());
+        if file.name().ends_with('/') {
+            fs::create_dir_all(&path)?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut df = fs::File::create(&path)?;
+        io::copy(&mut file, &mut df)?;
+        if let Some(mode) = file.unix_mode() {
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+        }
+    }
+    Ok(())
+}
+
+fn unpack(archive: &Path, dst: &Path) -> Result<()> {
+    let name = archive.file_name().and_then(|x| x.to_str()).unwrap_or("");
+    if name.ends_with(".zip") {
+        unpack_zip(archive, dst)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        let f = fs::File::open(archive)?;
+        tar::Archive::new(GzDecoder::new(f)?).unpack(dst)?;
+        Ok(())
+    } else if name.ends_with(".tar") {
+        tar::Archive::new(fs::File::open(archive)?).unpack(dst)?;
+        Ok(())
+    } else {
+        Err(Error::from(format!("unsupported archive format '{}'", archive.display())))
+    }
+}
+
+/// Extracts an archive into a folder after verifying its digest.
+///
+/// The folder is expected to be keyed by the digest so if it exists
+/// already the archive was verified before and nothing is extracted
+/// again.
+pub fn extract_archive(archive: &Path, dst: &Path, sha256: Option<&str>) -> Result<()> {
+    if fs::metadata(dst).is_ok() {
+        return Ok(());
+    }
+
+    let digest = get_file_sha256(archive)
+        .chain_err(|| format!("could not read archive {}", archive.display()))?;
+    if let Some(expected) = sha256 {
+        if expected.to_lowercase() != digest {
+            fail!("sha256 mismatch for {}: expected {}, got {}",
+                  archive.display(), expected, digest);
+        }
+    }
+
+    // extract into a scratch folder first so that a failed extraction
+    // never leaves a half populated include behind.
+    let mut tmp = PathBuf::from(dst);
+    tmp.set_extension("partial");
+    if fs::metadata(&tmp).is_ok() {
+        fs::remove_dir_all(&tmp)?;
+    }
+    fs::create_dir_all(&tmp)?;
+    if let Err(err) = unpack(archive, &tmp) {
+        fs::remove_dir_all(&tmp).ok();
+        return Err(err);
+    }
+    fs::rename(&tmp, dst)?;
+    Ok(())
+}
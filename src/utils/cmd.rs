@@ -1,10 +1,10 @@
 use std::io::{Read, BufRead, BufReader, Write};
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use std::ffi::{OsStr, OsString};
-use std::env;
 use std::process;
 use std::borrow::Cow;
 use std::sync::Arc;
+use std::collections::HashMap;
 
 use indicatif::{ProgressBar, ProgressStyle};
 use crossbeam;
@@ -178,57 +178,104 @@ impl CommandBuilder {
         self
     }
 
-    pub fn search_path(&mut self, paths: &[PathBuf]) -> &mut CommandBuilder {
-        let mut path = String::new();
-        for item in paths {
-            path.push_str(&format!("{}:", item.display()));
-        }
-        if let Ok(default_path) = env::var("PATH") {
-            path.push_str(&default_path);
-        }
-        self.cmd.env("PATH", path);
-        self
-    }
-
     pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(&mut self, key: K, value: V) -> &mut CommandBuilder {
         self.cmd.env(key, value);
         self
     }
 
-    pub fn spawn(&mut self) -> Result<Command> {
-        self.cmd.stdout(process::Stdio::piped());
-        self.cmd.stderr(process::Stdio::piped());
-
+    fn add_args(&mut self) {
         if let Some(ref cmdline) = self.cmdline {
-            let mut cmdline = cmdline.to_string();
-            for arg in &self.args {
-                cmdline.push_str(&format!(" \"{}\"", arg.to_string_lossy()));
+            // arguments are passed as positional parameters so that the
+            // shell does not interpret them
+            if self.args.is_empty() {
+                self.cmd.arg(cmdline);
+            } else {
+                self.cmd.arg(format!("{} \"$@\"", cmdline));
+                self.cmd.arg("sh");
+                for arg in &self.args {
+                    self.cmd.arg(&arg);
+                }
             }
-            self.cmd.arg(cmdline);
         } else {
             for arg in &self.args {
                 self.cmd.arg(&arg);
             }
         }
+    }
 
+    pub fn spawn(&mut self) -> Result<Command> {
+        self.cmd.stdout(process::Stdio::piped());
+        self.cmd.stderr(process::Stdio::piped());
+        self.add_args();
         Ok(Command::new(self.cmd.spawn()?, self.cmd_name.clone()))
     }
+
+    /// Runs the command attached to the terminal and waits for it.
+    pub fn run_attached(&mut self) -> Result<process::ExitStatus> {
+        self.cmd.stdin(process::Stdio::inherit());
+        self.cmd.stdout(process::Stdio::inherit());
+        self.cmd.stderr(process::Stdio::inherit());
+        self.add_args();
+        Ok(self.cmd.status()?)
+    }
+}
+
+lazy_static! {
+    static ref VAR_RE: Regex = Regex::new(
+        r"\$(\$|[a-zA-Z0-9_]+|\([^)]+\)|\{[^}]+\})").unwrap();
+}
+
+fn var_key(s: &str) -> &str {
+    if &s[..1] == "(" || &s[..1] == "{" {
+        &s[1..s.len() - 1]
+    } else {
+        s
+    }
 }
 
 /// Expands variables in a string
 pub fn expand_vars<'a, F: Fn(&str) -> String>(s: &'a str, f: F) -> Cow<'a, str> {
-    lazy_static! {
-        static ref VAR_RE: Regex = Regex::new(
-            r"\$(\$|[a-zA-Z0-9_]+|\([^)]+\)|\{[^}]+\})").unwrap();
-    }
     VAR_RE.replace_all(s, |caps: &Captures| {
         let key = &caps[1];
         if key == "$" {
             "$".into()
-        } else if &key[..1] == "(" || &key[..1] == "{" {
-            f(&key[1..key.len() - 1])
         } else {
-            f(key)
+            f(var_key(key))
         }
     })
 }
+
+/// Quotes a string so that `sh` reads it as a single word.
+pub fn quote_shell_arg(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+fn expand_known_vars_with<'a, F>(s: &'a str, vars: &HashMap<String, String>, f: F)
+    -> Cow<'a, str>
+    where F: Fn(&str) -> String
+{
+    VAR_RE.replace_all(s, |caps: &Captures| {
+        let key = &caps[1];
+        if key == "$" {
+            return "$".into();
+        }
+        match vars.get(var_key(key)) {
+            Some(value) => f(value),
+            None => caps[0].to_string(),
+        }
+    })
+}
+
+/// Expands only the given variables in a string and leaves everything
+/// else (like shell variables) untouched.  `$$` is a literal `$`.
+pub fn expand_known_vars<'a>(s: &'a str, vars: &HashMap<String, String>) -> Cow<'a, str> {
+    expand_known_vars_with(s, vars, |value| value.to_string())
+}
+
+/// Like `expand_known_vars` but quotes the values so that they are
+/// passed as single words to `sh`.
+pub fn expand_known_shell_vars<'a>(s: &'a str, vars: &HashMap<String, String>)
+    -> Cow<'a, str>
+{
+    expand_known_vars_with(s, vars, |value| quote_shell_arg(value))
+}
@@ -0,0 +1,126 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use git2;
+use git2::{Cred, FetchOptions, ObjectType, Oid, RemoteCallbacks, Repository};
+use git2::build::CheckoutBuilder;
+
+use prelude::*;
+
+
+const FETCH_REFSPECS: &'static [&'static str] = &[
+    "+HEAD:refs/remotes/origin/HEAD",
+    "+refs/heads/*:refs/remotes/origin/*",
+    "+refs/tags/*:refs/tags/*",
+];
+
+fn fetch(repo: &Repository, url: &str) -> Result<()> {
+    let mut remote = match repo.find_remote("origin") {
+        Ok(remote) => remote,
+        Err(_) => repo.remote("origin", url)?,
+    };
+
+    // libgit2 asks again with the same allowed types if a credential
+    // is rejected so every kind is only tried once.
+    let mut tried_agent = false;
+    let mut tried_helper = false;
+    let mut tried_default = false;
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(|url, username, allowed| {
+        if allowed.contains(git2::SSH_KEY) && !tried_agent {
+            tried_agent = true;
+            Cred::ssh_key_from_agent(username.unwrap_or("git"))
+        } else if allowed.contains(git2::USER_PASS_PLAINTEXT) && !tried_helper {
+            tried_helper = true;
+            Cred::credential_helper(&git2::Config::open_default()?, url, username)
+        } else if !tried_default {
+            tried_default = true;
+            Cred::default()
+        } else {
+            Err(git2::Error::from_str(&format!("failed to authenticate with {}", url)))
+        }
+    });
+    let mut opts = FetchOptions::new();
+    opts.remote_callbacks(callbacks);
+
+    remote.fetch(FETCH_REFSPECS, Some(&mut opts), None)?;
+    Ok(())
+}
+
+fn resolve_rev(repo: &Repository, rev: Option<&str>) -> Result<Oid> {
+    let candidates = match rev {
+        Some(rev) => vec![
+            format!("refs/remotes/origin/{}", rev),
+            format!("refs/tags/{}", rev),
+            rev.to_string(),
+        ],
+        None => vec!["refs/remotes/origin/HEAD".to_string()],
+    };
+    for candidate in candidates {
+        if let Ok(obj) = repo.revparse_single(&candidate) {
+            return Ok(obj.peel(ObjectType::Commit)?.id());
+        }
+    }
+    fail!("could not resolve revision '{}'", rev.unwrap_or("HEAD"));
+}
+
+/// Fetches a git repository into a folder and checks out a revision.
+///
+/// The revision can be a branch, tag or commit sha.  If a commit is
+/// given it's checked out instead of resolving the revision and the
+/// network is only accessed if the commit is not available yet.  The
+/// sha of the commit that was checked out is returned.
+pub fn checkout_rev(path: &Path, url: &str, rev: Option<&str>,
+                    commit: Option<&str>, offline: bool) -> Result<String> {
+    let repo = match Repository::open(path) {
+        Ok(repo) => repo,
+        Err(_) => {
+            if offline {
+                fail!("cannot clone {} in offline mode", url);
+            }
+            fs::create_dir_all(path)?;
+            Repository::init(path)?
+        }
+    };
+
+    let oid = match commit {
+        Some(commit) => {
+            let oid = Oid::from_str(commit)?;
+            if repo.find_commit(oid).is_err() {
+                if offline {
+                    fail!("commit {} of {} is not available offline", commit, url);
+                }
+                fetch(&repo, url)?;
+            }
+            oid
+        }
+        None => {
+            if !offline {
+                fetch(&repo, url)?;
+            }
+            resolve_rev(&repo, rev)?
+        }
+    };
+
+    let obj = repo.find_object(oid, None)?.peel(ObjectType::Commit)?;
+    repo.checkout_tree(&obj, Some(CheckoutBuilder::new().force()))?;
+    repo.set_head_detached(obj.id())?;
+    Ok(obj.id().to_string())
+}
+
+/// Returns the sha of the commit that is checked out in a folder.
+pub fn get_head_commit(path: &Path) -> Result<Option<String>> {
+    let repo = match Repository::open(path) {
+        Ok(repo) => repo,
+        Err(_) => return Ok(None),
+    };
+    let head = repo.head()?;
+    Ok(head.target().map(|x| x.to_string()))
+}
+
+/// Returns the working directory of the git repository the current
+/// folder is in.
+pub fn find_repo_root() -> Option<PathBuf> {
+    Repository::open_from_env().ok()
+        .and_then(|repo| repo.workdir().map(|x| x.to_path_buf()))
+}
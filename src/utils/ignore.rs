@@ -0,0 +1,72 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use git2::Repository;
+use parking_lot::Mutex;
+
+use utils::serde::Pattern;
+
+
+/// Checks paths against the ignore rules of the git repository they
+/// live in.
+pub struct GitIgnore {
+    repo: Mutex<Repository>,
+    workdir: PathBuf,
+}
+
+impl fmt::Debug for GitIgnore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GitIgnore")
+            .field("workdir", &self.workdir)
+            .finish()
+    }
+}
+
+impl GitIgnore {
+    /// Opens the repository a folder belongs to.  Returns `None` if
+    /// the folder is not in a git work tree.
+    pub fn discover(path: &Path) -> Option<GitIgnore> {
+        let repo = match Repository::discover(path) {
+            Ok(repo) => repo,
+            Err(_) => return None,
+        };
+        let workdir = match repo.workdir().and_then(|x| x.canonicalize().ok()) {
+            Some(workdir) => workdir,
+            None => return None,
+        };
+        Some(GitIgnore {
+            repo: Mutex::new(repo),
+            workdir: workdir,
+        })
+    }
+
+    /// Checks if git ignores a path.  The `.git` folder itself is
+    /// always ignored.
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let rel = match path.strip_prefix(&self.workdir) {
+            Ok(rel) => rel,
+            Err(_) => return false,
+        };
+        if rel.components().next().map(|x| x.as_os_str() == ".git").unwrap_or(false) {
+            return true;
+        }
+        self.repo.lock().status_should_ignore(rel).unwrap_or(false)
+    }
+}
+
+/// Checks if a relative path or one of its parent folders matches any
+/// of the given patterns.
+pub fn is_path_excluded(patterns: &[Pattern], path: &Path) -> bool {
+    let mut current = Some(path);
+    while let Some(path) = current {
+        if path.as_os_str().is_empty() {
+            break;
+        }
+        if patterns.iter().any(|x| x.match_path(path)) {
+            return true;
+        }
+        current = path.parent();
+    }
+    false
+}
@@ -1,4 +1,7 @@
+pub mod archive;
 pub mod cmd;
+pub mod git;
+pub mod ignore;
 pub mod serde;
 pub mod whatchanged;
 pub mod hooks;
@@ -104,6 +104,15 @@ impl<'a> Deserialize<'a> for Pattern {
     }
 }
 
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Pattern::Glob(ref pattern) => write!(f, "{}", pattern.as_str()),
+            Pattern::Regex(ref pattern) => write!(f, "/{}/", pattern.as_str()),
+        }
+    }
+}
+
 impl Pattern {
     pub fn match_path<P: AsRef<Path>>(&self, p: P) -> bool {
         match *self {
@@ -1,7 +1,29 @@
 use std::io;
 use std::io::Write;
 
+use prelude::*;
+
 
 pub fn clear_term() {
     write!(&mut io::stderr(), "\x1b[2J\x1b[H").ok();
 }
+
+/// Asks a yes/no question on the terminal.  An empty answer picks the
+/// default.
+pub fn prompt_yes_no(question: &str, default: bool) -> Result<bool> {
+    loop {
+        write!(&mut io::stderr(), "{} [{}] ", question,
+               if default { "Y/n" } else { "y/N" })?;
+        io::stderr().flush()?;
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer)? == 0 {
+            return Ok(default);
+        }
+        match answer.trim().to_lowercase().as_str() {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => continue,
+        }
+    }
+}
@@ -6,6 +6,10 @@ use notify::{Watcher, RecursiveMode, DebouncedEvent, watcher};
 
 use prelude::*;
 
+fn is_git_path(path: &Path) -> bool {
+    path.components().any(|x| x.as_os_str() == ".git")
+}
+
 pub fn watch_files(path: &Path, cb: &Fn(&Path) -> Result<()>) -> Result<()> {
     let (tx, rx) = channel();
     let mut watcher = watcher(tx, Duration::from_millis(100)).unwrap();
@@ -14,6 +18,9 @@ pub fn watch_files(path: &Path, cb: &Fn(&Path) -> Result<()>) -> Result<()> {
 
     loop {
         match rx.recv() {
+            Ok(DebouncedEvent::Create(ref path)) if is_git_path(path) => {}
+            Ok(DebouncedEvent::Write(ref path)) if is_git_path(path) => {}
+            Ok(DebouncedEvent::Rename(_, ref path)) if is_git_path(path) => {}
             Ok(DebouncedEvent::Create(path)) => { cb(&path)? }
             Ok(DebouncedEvent::Write(path)) => { cb(&path)? }
             Ok(DebouncedEvent::Rename(_, path)) => { cb(&path)? }
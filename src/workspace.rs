@@ -0,0 +1,283 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use prelude::*;
+use config::{Config, fail_unknown_profile, find_config_file};
+use ctx::{Context, ToolFilter};
+use report::Report;
+use formatting::FormatResult;
+
+use strsim::levenshtein;
+use walkdir::{DirEntry, WalkDir};
+
+/// A workspace is a tree of projects with their own calm config.  Every
+/// file belongs to the project with the deepest config above it.
+#[derive(Debug)]
+pub struct Workspace {
+    root: PathBuf,
+    contexts: Vec<Context>,
+    primary: usize,
+    profile: Option<String>,
+}
+
+fn is_ignored_dir(entry: &DirEntry) -> bool {
+    entry.depth() > 0 && entry.file_type().is_dir() && entry.file_name()
+        .to_str()
+        .map(|x| x.starts_with('.') || x == "node_modules" || x == "target")
+        .unwrap_or(false)
+}
+
+/// Finds the configs of projects below a project.  Folders the project
+/// excludes or git ignores are not searched.
+fn find_nested_configs(ctx: &Context) -> Vec<PathBuf> {
+    let mut rv = vec![];
+    let walker = WalkDir::new(ctx.base_dir()).min_depth(1).into_iter()
+        .filter_entry(|x| {
+            x.file_type().is_dir() && !is_ignored_dir(x) && !ctx.is_excluded(x.path())
+        });
+    for entry in walker {
+        if_chain! {
+            if let Ok(entry) = entry;
+            if entry.path().join(".calm/calm.yml").is_file();
+            then {
+                rv.push(entry.path().join(".calm/calm.yml"));
+            }
+        }
+    }
+    rv
+}
+
+fn canonical_dir(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// The files a context has to process.
+enum FileSelection {
+    Skip,
+    All,
+    Files(Vec<PathBuf>),
+}
+
+fn canonical_path(path: &Path) -> Result<PathBuf> {
+    let path = env::current_dir()?.join(path);
+    Ok(path.canonicalize().unwrap_or(path))
+}
+
+impl Workspace {
+    /// Finds the config of the current folder.  With `nested` the
+    /// configs of projects below it are loaded too.  All configs are
+    /// loaded with the given profile and offline mode.
+    pub fn discover(profile: Option<&str>, nested: bool, offline: bool) -> Result<Workspace> {
+        let filename = find_config_file()?;
+        let root_ctx = Context::new(Config::load(&filename, profile, offline)?)?;
+        let root = root_ctx.base_dir().to_path_buf();
+
+        let mut contexts = vec![];
+        if nested {
+            for filename in find_nested_configs(&root_ctx) {
+                match Config::load(&filename, profile, offline).and_then(Context::new) {
+                    Ok(ctx) => contexts.push(ctx),
+                    Err(err) => {
+                        println_stderr!("warning: skipping {}: {}", filename.display(), err);
+                    }
+                }
+            }
+        }
+        contexts.push(root_ctx);
+
+        // projects without the profile run all their tools but at least
+        // one of them has to know it
+        if let Some(profile) = profile {
+            if !contexts.iter().any(|x| x.config().profile() == Some(profile)) {
+                let mut names: Vec<String> = contexts.iter()
+                    .flat_map(|x| x.config().profile_names())
+                    .collect();
+                names.sort();
+                names.dedup();
+                fail_unknown_profile(profile, &names)?;
+            }
+        }
+
+        // deepest projects first so routing picks the nearest config
+        contexts.sort_by(|a, b| {
+            b.base_dir().components().count().cmp(&a.base_dir().components().count())
+        });
+
+        let here = env::current_dir()?;
+        let primary = contexts.iter()
+            .position(|x| here.starts_with(x.base_dir()))
+            .unwrap_or(contexts.len() - 1);
+
+        Ok(Workspace {
+            root: root,
+            contexts: contexts,
+            primary: primary,
+            profile: profile.map(|x| x.to_string()),
+        })
+    }
+
+    /// The folder the workspace was discovered from.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn contexts(&self) -> &[Context] {
+        &self.contexts
+    }
+
+    pub fn contexts_mut(&mut self) -> &mut [Context] {
+        &mut self.contexts
+    }
+
+    /// The context of the config the current folder belongs to.
+    pub fn primary(&self) -> &Context {
+        &self.contexts[self.primary]
+    }
+
+    pub fn primary_mut(&mut self) -> &mut Context {
+        &mut self.contexts[self.primary]
+    }
+
+    pub fn clear_log(&self) {
+        for ctx in &self.contexts {
+            ctx.clear_log();
+        }
+    }
+
+    /// Restricts all projects to the given tools.  Fails if a tool id
+    /// is not known to any project.
+    pub fn set_tool_filter(&mut self, filter: ToolFilter) -> Result<()> {
+        let mut known: Vec<String> = self.contexts.iter()
+            .flat_map(|x| x.config().iter_tools().cloned().collect::<Vec<_>>())
+            .collect();
+        known.sort();
+        known.dedup();
+
+        for tool_id in filter.tool_ids() {
+            if known.iter().any(|x| x == tool_id) {
+                continue;
+            }
+            match known.iter().min_by_key(|x| levenshtein(tool_id, x)) {
+                Some(suggestion) if levenshtein(tool_id, suggestion) <= 3 => {
+                    fail!("unknown tool '{}', did you mean '{}'?", tool_id, suggestion);
+                }
+                _ => {
+                    fail!("unknown tool '{}' (available: {})", tool_id, known.join(", "));
+                }
+            }
+        }
+
+        for ctx in &mut self.contexts {
+            ctx.set_tool_filter(filter.clone());
+        }
+        Ok(())
+    }
+
+    /// Switches all projects to the profiles configured for a git hook
+    /// unless a profile was selected explicitly.
+    pub fn use_hook_profile(&mut self, hook: &str) -> Result<()> {
+        if self.profile.is_some() {
+            return Ok(());
+        }
+        for ctx in &mut self.contexts {
+            ctx.use_hook_profile(hook)?;
+        }
+        Ok(())
+    }
+
+    pub fn ensure_toolchains(&mut self) -> Result<()> {
+        for ctx in &mut self.contexts {
+            ctx.ensure_toolchains()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the index of the context that owns a file.
+    fn find_owner(&self, path: &Path) -> usize {
+        for (idx, ctx) in self.contexts.iter().enumerate() {
+            if path.starts_with(&canonical_dir(ctx.base_dir())) {
+                return idx;
+            }
+        }
+        self.primary
+    }
+
+    /// Splits files up by the context that owns them.
+    fn route_files(&self, files: &[&Path]) -> Result<Vec<Vec<PathBuf>>> {
+        let mut rv = vec![vec![]; self.contexts.len()];
+        for file in files {
+            let path = canonical_path(file)?;
+            rv[self.find_owner(&path)].push(path);
+        }
+        Ok(rv)
+    }
+
+    /// The folders of the projects nested in a project.
+    fn nested_dirs(&self, idx: usize) -> Vec<PathBuf> {
+        let base = self.contexts[idx].base_dir();
+        self.contexts.iter().enumerate()
+            .filter(|&(other, ctx)| other != idx && ctx.base_dir().starts_with(base))
+            .map(|(_, ctx)| canonical_dir(ctx.base_dir()))
+            .collect()
+    }
+
+    /// Figures out which files each context should handle.
+    fn select_files(&self, files: Option<&[&Path]>) -> Result<Vec<FileSelection>> {
+        let routed = match files {
+            Some(files) => Some(self.route_files(files)?),
+            None => None,
+        };
+        Ok((0..self.contexts.len()).map(|idx| {
+            match routed {
+                Some(ref routed) if routed[idx].is_empty() => FileSelection::Skip,
+                Some(ref routed) => FileSelection::Files(routed[idx].clone()),
+                None => FileSelection::All,
+            }
+        }).collect())
+    }
+
+    /// Lints the files of all projects and merges the results into one
+    /// report.
+    pub fn lint(&self, files: Option<&[&Path]>) -> Result<Report> {
+        let mut report = Report::new(self.primary());
+        let selections = self.select_files(files)?;
+        for (idx, (ctx, selection)) in self.contexts.iter().zip(selections).enumerate() {
+            report.merge(match selection {
+                FileSelection::Skip => continue,
+                FileSelection::All => {
+                    // nested projects lint their own files
+                    let nested = self.nested_dirs(idx);
+                    let mut ctx_report = ctx.lint(None)?;
+                    ctx_report.remove_files_below(
+                        &nested.iter().map(|x| x.as_path()).collect::<Vec<_>>());
+                    ctx_report
+                }
+                FileSelection::Files(ctx_files) => {
+                    let paths: Vec<&Path> = ctx_files.iter().map(|x| x.as_path()).collect();
+                    ctx.lint(Some(&paths[..]))?
+                }
+            });
+        }
+        report.sort();
+        Ok(report)
+    }
+
+    /// Formats the files with the formatters of the projects they
+    /// belong to.
+    pub fn format(&self, files: &[&Path]) -> Result<FormatResult> {
+        let mut rv = FormatResult::new();
+        for (ctx, ctx_files) in self.contexts.iter().zip(self.route_files(files)?) {
+            if ctx_files.is_empty() {
+                continue;
+            }
+            let paths: Vec<&Path> = ctx_files.iter().map(|x| x.as_path()).collect();
+            rv.merge(ctx.format(&paths)?);
+        }
+        Ok(rv)
+    }
+
+    pub fn is_lintable_file(&self, path: &Path) -> Result<bool> {
+        let path = canonical_path(path)?;
+        self.contexts[self.find_owner(&path)].is_lintable_file(&path)
+    }
+}